@@ -0,0 +1,934 @@
+use async_trait::async_trait;
+use bsky_sdk::api::{
+    agent::Session,
+    app::bsky::{
+        embed::{
+            defs::AspectRatioData,
+            images::{ImageData, MainData},
+        },
+        feed::{
+            defs::{ThreadViewPost, ThreadViewPostRepliesItem},
+            get_author_feed::ParametersData,
+            get_post_thread, get_posts,
+            post::{RecordData, RecordEmbedRefs, ReplyRef, ReplyRefData},
+        },
+    },
+    com::atproto::{
+        identity::resolve_handle,
+        repo::{delete_record, put_record, strong_ref},
+    },
+    types::{
+        string::{Datetime, Did},
+        TryFromUnknown, Union,
+    },
+    xrpc::error::{Error, XrpcErrorKind},
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use serde_json::json;
+use std::num::NonZeroU64;
+
+use crate::config::Config;
+use crate::publisher::{
+    count_graphemes, post_marker, truncate_text, DailyReport, ExistingPost, Publisher,
+};
+use crate::richtext::RichText;
+use crate::session::Agent;
+use crate::{compressor, entsoe, get_resolution, plotter, profile, session};
+
+// Bluesky's limits for post text in graphemes and image alt text in characters
+static MAX_TEXT_LENGTH: usize = 300;
+static MAX_ALT_TEXT_LENGTH: usize = 2000;
+
+// collection of the machine-readable price records, see lexicons/
+static DAY_AHEAD_COLLECTION: &str = "app.spotbot.dayAhead";
+
+// upper limit for paging the author feed when looking for an existing post
+static MAX_FEED_PAGES: usize = 20;
+
+// reply depth fetched when deleting a post, enough for the price table
+static MAX_THREAD_DEPTH: u16 = 50;
+
+// Pages through the account's own posts back to `since`, looking for a post
+// tagged with the marker, or with the title in its text for posts made
+// before markers were added.
+pub async fn check_post_exists(
+    agent: &Agent,
+    session: &Session,
+    marker: &str,
+    title: &str,
+    since: DateTime<Utc>,
+) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+    let since = Datetime::new(since.fixed_offset());
+    let mut cursor = None;
+
+    for _ in 0..MAX_FEED_PAGES {
+        let own_posts_feed = agent
+            .api
+            .app
+            .bsky
+            .feed
+            .get_author_feed(
+                ParametersData {
+                    actor: session.data.handle.clone().into(),
+                    cursor,
+                    filter: Some("posts_no_replies".to_string()),
+                    include_pins: None,
+                    limit: Some(50.try_into().unwrap()),
+                }
+                .into(),
+            )
+            .await?;
+
+        // reposts are not own posts
+        for item in own_posts_feed
+            .data
+            .feed
+            .iter()
+            .filter(|i| i.reason.is_none())
+        {
+            let post = &item.post;
+            if post.indexed_at < since {
+                return Ok(None);
+            }
+
+            let Ok(record) = RecordData::try_from_unknown(post.record.clone()) else {
+                continue;
+            };
+            let matched_by = if record
+                .tags
+                .is_some_and(|tags| tags.iter().any(|tag| tag == marker))
+            {
+                "marker"
+            } else if record.text.contains(title) {
+                "title"
+            } else {
+                continue;
+            };
+
+            return Ok(Some(ExistingPost {
+                uri: post.uri.clone(),
+                matched_by,
+            }));
+        }
+
+        match own_posts_feed.data.cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(None)
+}
+
+// handles with their DIDs for mention facets
+pub async fn resolve_mentions(
+    agent: &Agent,
+    handles: &[String],
+) -> Result<Vec<(String, Did)>, Box<dyn std::error::Error>> {
+    let mut mentions = Vec::new();
+    for handle in handles {
+        let output = agent
+            .api
+            .com
+            .atproto
+            .identity
+            .resolve_handle(
+                resolve_handle::ParametersData {
+                    handle: handle.parse()?,
+                }
+                .into(),
+            )
+            .await?;
+        mentions.push((handle.clone(), output.data.did));
+    }
+    Ok(mentions)
+}
+
+// post text followed by the optional link, mentions and hashtags, each on their own line
+pub fn compose_rich_text(
+    text: String,
+    link: Option<&str>,
+    mentions: &[(String, Did)],
+    tags: &[String],
+) -> RichText {
+    let mut rich_text = RichText::new().text(&text);
+    let mut separator = "\n\n";
+
+    if let Some(uri) = link {
+        let shown = uri
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        rich_text = rich_text.text(separator).link(shown, uri);
+        separator = "\n";
+    }
+    if !mentions.is_empty() {
+        rich_text = rich_text
+            .text(separator)
+            .join(mentions, " ", |rt, (handle, did)| {
+                rt.mention(handle, did.clone())
+            });
+        separator = "\n";
+    }
+    if !tags.is_empty() {
+        rich_text = rich_text
+            .text(separator)
+            .join(tags, " ", |rt, tag| rt.tag(tag));
+    }
+
+    rich_text
+}
+
+pub async fn post(
+    agent: &Agent,
+    image: Vec<u8>,
+    rich_text: RichText,
+    alt_text: String,
+    marker: String,
+    reply: Option<ReplyRef>,
+) -> Result<strong_ref::Main, Box<dyn std::error::Error>> {
+    let image = compressor::compress(image, compressor::MAX_IMAGE_BYTES)?;
+
+    let output = agent.api.com.atproto.repo.upload_blob(image.bytes).await?;
+
+    let aspect_ratio = match (
+        NonZeroU64::new(image.width.into()),
+        NonZeroU64::new(image.height.into()),
+    ) {
+        (Some(width), Some(height)) => Some(AspectRatioData { width, height }.into()),
+        _ => None,
+    };
+
+    let image_data = ImageData {
+        alt: alt_text,
+        aspect_ratio,
+        image: output.data.blob,
+    };
+
+    let images = vec![image_data.into()];
+
+    let embed = Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(
+        Box::new(MainData { images }.into()),
+    )));
+
+    log::info!("Posting: {}", rich_text.text);
+
+    let output = agent
+        .create_record(RecordData {
+            created_at: Datetime::now(),
+            embed,
+            entities: None,
+            facets: (!rich_text.facets.is_empty()).then_some(rich_text.facets),
+            labels: None,
+            langs: None,
+            reply,
+            tags: Some(vec![marker]),
+            text: rich_text.text,
+        })
+        .await?;
+    Ok(strong_ref::MainData {
+        cid: output.data.cid,
+        uri: output.data.uri,
+    }
+    .into())
+}
+
+// Replies to parent in the thread starting from root. Returns a reference to the
+// reply for continuing the thread.
+pub async fn reply(
+    agent: &Agent,
+    rich_text: RichText,
+    root: strong_ref::Main,
+    parent: strong_ref::Main,
+) -> Result<strong_ref::Main, Box<dyn std::error::Error>> {
+    log::info!("Replying: {}", rich_text.text);
+
+    let output = agent
+        .create_record(RecordData {
+            created_at: Datetime::now(),
+            embed: None,
+            entities: None,
+            facets: (!rich_text.facets.is_empty()).then_some(rich_text.facets),
+            labels: None,
+            langs: None,
+            reply: Some(ReplyRefData { root, parent }.into()),
+            tags: None,
+            text: rich_text.text,
+        })
+        .await?;
+    Ok(strong_ref::MainData {
+        cid: output.data.cid,
+        uri: output.data.uri,
+    }
+    .into())
+}
+
+// the day's prices as an app.spotbot.dayAhead record
+fn compose_day_ahead_record(
+    report: &DailyReport,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let start = report.raw_prices.first().ok_or("No prices to write")?.0;
+    let mut record = json!({
+        "$type": DAY_AHEAD_COLLECTION,
+        "area": report.area,
+        "deliveryDate": report.day.format("%Y-%m-%d").to_string(),
+        "start": start.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "resolution": format!("PT{}M", get_resolution(&report.raw_prices).num_minutes()),
+        "currency": "EUR",
+        "unit": "MWh",
+        "vat": (report.vat * 100.0).round() as i64,
+        "prices": report
+            .raw_prices
+            .iter()
+            .map(|(_, price)| (price * 100.0).round() as i64)
+            .collect::<Vec<_>>(),
+        "createdAt": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    });
+    if let Some((area_name, _)) = entsoe::get_area(&report.area) {
+        record["areaName"] = json!(area_name);
+    }
+    Ok(record)
+}
+
+// Writes the day's prices as an app.spotbot.dayAhead record. The record key
+// is the area and day, so writing again replaces the record.
+pub async fn put_day_ahead_record(
+    agent: &Agent,
+    session: &Session,
+    report: &DailyReport,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let record = compose_day_ahead_record(report)?;
+
+    let output = agent
+        .api
+        .com
+        .atproto
+        .repo
+        .put_record(
+            put_record::InputData {
+                collection: DAY_AHEAD_COLLECTION.parse()?,
+                record: serde_json::from_value(record)?,
+                repo: session.data.did.clone().into(),
+                rkey: format!("{}_{}", report.area, report.day.format("%Y-%m-%d")),
+                swap_commit: None,
+                swap_record: None,
+                // the PDS doesn't know the lexicon
+                validate: Some(false),
+            }
+            .into(),
+        )
+        .await?;
+    Ok(output.data.uri)
+}
+
+// the fixed description followed by as much of the report as fits, within
+// the profile's grapheme limit
+fn compose_profile_description(description: Option<&str>, report: &DailyReport) -> String {
+    let max_length = profile::MAX_DESCRIPTION_LENGTH;
+    let Some(description) = description else {
+        return report.fit_text(max_length, count_graphemes);
+    };
+    let intro = format!("{}\n\n", description);
+    let text = report.fit_text(
+        max_length.saturating_sub(count_graphemes(&intro)),
+        count_graphemes,
+    );
+    if text.is_empty() {
+        // no room left for the prices
+        truncate_text(description, max_length, count_graphemes)
+    } else {
+        intro + &text
+    }
+}
+
+pub struct BlueskyPublisher {
+    agent: Agent,
+    // None when not authenticated, e.g. in dry runs
+    session: Option<Session>,
+    mentions: Vec<(String, Did)>,
+    price_table: bool,
+    record: bool,
+    profile_update: bool,
+    profile_description: Option<String>,
+    profile_banner: bool,
+}
+
+impl BlueskyPublisher {
+    pub async fn new(
+        config: &Config,
+        authenticate: bool,
+    ) -> Result<BlueskyPublisher, Box<dyn std::error::Error>> {
+        let (agent, session) = if authenticate {
+            let (username, password) = config.bluesky_credentials()?;
+            let (agent, session) =
+                session::get_agent(username, password, &config.session_file).await?;
+            (agent, Some(session))
+        } else {
+            (session::anonymous_agent().await?, None)
+        };
+        let mentions = resolve_mentions(&agent, &config.post_mentions).await?;
+
+        Ok(BlueskyPublisher {
+            agent,
+            session,
+            mentions,
+            price_table: config.post_price_table,
+            record: config.post_record,
+            profile_update: config.profile_update,
+            profile_description: config.profile_description.clone(),
+            profile_banner: config.profile_banner,
+        })
+    }
+
+    // report text within the grapheme limit, followed by the link, mentions and tags
+    fn compose(&self, report: &DailyReport) -> RichText {
+        let extras = compose_rich_text(
+            "".to_string(),
+            report.link.as_deref(),
+            &self.mentions,
+            &report.tags,
+        );
+        let text = report.fit_text(
+            MAX_TEXT_LENGTH.saturating_sub(count_graphemes(&extras.text)),
+            count_graphemes,
+        );
+        compose_rich_text(text, report.link.as_deref(), &self.mentions, &report.tags)
+    }
+
+    async fn update_profile(&self, report: &DailyReport) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.profile_update {
+            return Ok(());
+        }
+        let session = self.session.as_ref().ok_or("Not logged in to Bluesky")?;
+        let banner = if self.profile_banner {
+            Some(plotter::plot_sparkline(&report.prices, &report.aggregates)?)
+        } else {
+            None
+        };
+
+        profile::update_profile(
+            &self.agent,
+            session,
+            compose_profile_description(self.profile_description.as_deref(), report),
+            banner,
+        )
+        .await?;
+        log::info!("Updated profile");
+        Ok(())
+    }
+
+    // threaded replies with every slot's price
+    fn compose_replies(&self, report: &DailyReport) -> Vec<String> {
+        if self.price_table {
+            report.price_table.split(MAX_TEXT_LENGTH, count_graphemes)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Publisher for BlueskyPublisher {
+    fn name(&self) -> &'static str {
+        "bluesky"
+    }
+
+    async fn find_existing(
+        &self,
+        area: &str,
+        day: NaiveDate,
+        day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        let session = self.session.as_ref().ok_or("Not logged in to Bluesky")?;
+        // posts are made the day before delivery, or later for backfills
+        let since = (day - Duration::days(2)).and_time(NaiveTime::MIN).and_utc();
+        check_post_exists(
+            &self.agent,
+            session,
+            &post_marker(area, day),
+            day_title,
+            since,
+        )
+        .await
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let rich_text = self.compose(report);
+        let facets = rich_text
+            .facets
+            .iter()
+            .map(|facet| {
+                format!(
+                    "  {}..{} {:?}: {:?}",
+                    facet.index.byte_start,
+                    facet.index.byte_end,
+                    &rich_text.text[facet.index.byte_start..facet.index.byte_end],
+                    facet.features
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let replies = self
+            .compose_replies(report)
+            .iter()
+            .map(|reply| format!("\n\nReply:\n{}", reply))
+            .collect::<String>();
+
+        let profile = if self.profile_update {
+            format!(
+                "\n\nProfile description:\n{}",
+                compose_profile_description(self.profile_description.as_deref(), report)
+            )
+        } else {
+            "".to_string()
+        };
+        let record = if self.record {
+            format!(
+                "\n\nRecord:\n{}",
+                serde_json::to_string_pretty(&compose_day_ahead_record(report)?)?
+            )
+        } else {
+            "".to_string()
+        };
+
+        Ok(format!(
+            "{}\n\nFacets:\n{}\n\nAlt text:\n{}{}{}{}",
+            rich_text.text,
+            facets,
+            report.alt_text.fit(MAX_ALT_TEXT_LENGTH),
+            replies,
+            record,
+            profile
+        ))
+    }
+
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let root = post(
+            &self.agent,
+            report.image.clone(),
+            self.compose(report),
+            report.alt_text.fit(MAX_ALT_TEXT_LENGTH),
+            post_marker(&report.area, report.day),
+            None,
+        )
+        .await?;
+
+        let mut parent = root.clone();
+        for text in self.compose_replies(report) {
+            parent = reply(
+                &self.agent,
+                RichText::new().text(&text),
+                root.clone(),
+                parent,
+            )
+            .await?;
+        }
+
+        if self.record {
+            let session = self.session.as_ref().ok_or("Not logged in to Bluesky")?;
+            let uri = put_day_ahead_record(&self.agent, session, report).await?;
+            log::info!("Wrote price record: {}", uri);
+        }
+        self.update_profile(report).await?;
+
+        Ok(root.data.uri)
+    }
+
+    // the corrected report with a new chart as a reply to the post
+    async fn reply(
+        &self,
+        uri: &str,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let posts = self
+            .agent
+            .api
+            .app
+            .bsky
+            .feed
+            .get_posts(
+                get_posts::ParametersData {
+                    uris: vec![uri.to_string()],
+                }
+                .into(),
+            )
+            .await?;
+        let parent = posts.data.posts.first().ok_or("Post not found")?;
+        let parent: strong_ref::Main = strong_ref::MainData {
+            cid: parent.cid.clone(),
+            uri: parent.uri.clone(),
+        }
+        .into();
+
+        let reply = post(
+            &self.agent,
+            report.image.clone(),
+            self.compose(report),
+            report.alt_text.fit(MAX_ALT_TEXT_LENGTH),
+            post_marker(&report.area, report.day),
+            Some(
+                ReplyRefData {
+                    root: parent.clone(),
+                    parent,
+                }
+                .into(),
+            ),
+        )
+        .await?;
+
+        if self.record {
+            let session = self.session.as_ref().ok_or("Not logged in to Bluesky")?;
+            put_day_ahead_record(&self.agent, session, report).await?;
+        }
+        self.update_profile(report).await?;
+
+        Ok(reply.data.uri)
+    }
+
+    // deletes the post with its replies from the account, e.g. the price table
+    async fn delete(&self, uri: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let session = self.session.as_ref().ok_or("Not logged in to Bluesky")?;
+        let thread = self
+            .agent
+            .api
+            .app
+            .bsky
+            .feed
+            .get_post_thread(
+                get_post_thread::ParametersData {
+                    depth: Some(MAX_THREAD_DEPTH.try_into()?),
+                    parent_height: Some(0.try_into()?),
+                    uri: uri.to_string(),
+                }
+                .into(),
+            )
+            .await;
+        let replies = match thread {
+            Ok(output) => match output.data.thread {
+                Union::Refs(get_post_thread::OutputThreadRefs::AppBskyFeedDefsThreadViewPost(
+                    thread,
+                )) => own_replies(&thread, &session.data.did),
+                _ => Vec::new(),
+            },
+            // already deleted, e.g. by hand
+            Err(Error::XrpcResponse(response))
+                if matches!(
+                    response.error,
+                    Some(XrpcErrorKind::Custom(get_post_thread::Error::NotFound(_)))
+                ) =>
+            {
+                Vec::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // replies first, so that none are left behind if a delete fails
+        for reply in replies {
+            delete_post(&self.agent, session, &reply).await?;
+        }
+        delete_post(&self.agent, session, uri).await
+    }
+}
+
+// URIs of the account's own replies below the post, the deepest first
+fn own_replies(thread: &ThreadViewPost, did: &Did) -> Vec<String> {
+    let mut uris = Vec::new();
+    for reply in thread.replies.iter().flatten() {
+        if let Union::Refs(ThreadViewPostRepliesItem::ThreadViewPost(reply)) = reply {
+            if &reply.post.author.did == did {
+                uris.extend(own_replies(reply, did));
+                uris.push(reply.post.uri.clone());
+            }
+        }
+    }
+    uris
+}
+
+async fn delete_post(
+    agent: &Agent,
+    session: &Session,
+    uri: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // at://<repo>/<collection>/<rkey>
+    let Some([_, collection, rkey]) = uri
+        .strip_prefix("at://")
+        .map(|path| path.splitn(3, '/').collect::<Vec<_>>())
+        .and_then(|parts| <[&str; 3]>::try_from(parts).ok())
+    else {
+        return Err(format!("Invalid post URI: {}", uri).into());
+    };
+
+    log::info!("Deleting {}", uri);
+    agent
+        .api
+        .com
+        .atproto
+        .repo
+        .delete_record(
+            delete_record::InputData {
+                collection: collection.parse()?,
+                repo: session.data.did.clone().into(),
+                rkey: rkey.to_string(),
+                swap_commit: None,
+                swap_record: None,
+            }
+            .into(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::tests::sample_report;
+    use mockito::Matcher;
+    use serde_json::Value;
+
+    static DID: &str = "did:plc:spotbot";
+
+    // publisher logged in as spotbot.test on a mock server
+    async fn publisher(server: &mockito::Server) -> BlueskyPublisher {
+        let session = serde_json::from_value(json!({
+            "accessJwt": "access",
+            "refreshJwt": "refresh",
+            "handle": "spotbot.test",
+            "did": DID,
+        }))
+        .unwrap();
+        BlueskyPublisher {
+            agent: crate::session::tests::agent(server.url()).await,
+            session: Some(session),
+            mentions: Vec::new(),
+            price_table: true,
+            record: false,
+            profile_update: false,
+            profile_description: None,
+            profile_banner: false,
+        }
+    }
+
+    fn post_view(did: &str, rkey: &str, indexed_at: &str, record: Value) -> Value {
+        json!({
+            "uri": format!("at://{}/app.bsky.feed.post/{}", did, rkey),
+            "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm",
+            "author": { "did": did, "handle": "spotbot.test" },
+            "record": record,
+            "indexedAt": indexed_at,
+        })
+    }
+
+    fn thread_view(did: &str, rkey: &str, replies: Vec<Value>) -> Value {
+        json!({
+            "$type": "app.bsky.feed.defs#threadViewPost",
+            "post": post_view(did, rkey, "2025-01-01T12:00:00.000Z", json!({
+                "$type": "app.bsky.feed.post",
+                "text": rkey,
+                "createdAt": "2025-01-01T12:00:00.000Z",
+            })),
+            "replies": replies,
+        })
+    }
+
+    #[tokio::test]
+    async fn reposts_delete_the_price_table_replies() {
+        let mut server = mockito::Server::new_async().await;
+        let thread = thread_view(
+            DID,
+            "root",
+            vec![
+                thread_view(DID, "table1", vec![thread_view(DID, "table2", vec![])]),
+                thread_view("did:plc:someone", "question", vec![]),
+            ],
+        );
+        server
+            .mock("GET", "/xrpc/app.bsky.feed.getPostThread")
+            .match_query(Matcher::UrlEncoded(
+                "uri".to_string(),
+                format!("at://{}/app.bsky.feed.post/root", DID),
+            ))
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "thread": thread }).to_string())
+            .create_async()
+            .await;
+        let mut delete = |rkey: &str, hits: usize| {
+            server
+                .mock("POST", "/xrpc/com.atproto.repo.deleteRecord")
+                .match_body(Matcher::PartialJson(json!({
+                    "repo": DID,
+                    "collection": "app.bsky.feed.post",
+                    "rkey": rkey,
+                })))
+                .with_header("content-type", "application/json")
+                .with_body("{}")
+                .expect(hits)
+                .create()
+        };
+        let deleted = [delete("root", 1), delete("table1", 1), delete("table2", 1)];
+        let kept = delete("question", 0);
+
+        let publisher = publisher(&server).await;
+        publisher
+            .delete(&format!("at://{}/app.bsky.feed.post/root", DID))
+            .await
+            .unwrap();
+        for mock in deleted {
+            mock.assert_async().await;
+        }
+        kept.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn existing_posts_are_found_on_later_pages() {
+        let mut server = mockito::Server::new_async().await;
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        let marker = post_marker("10YFI-1--------U", day);
+        let feed_post = |rkey: &str, indexed_at: &str, tags: Vec<&str>| {
+            json!({
+                "post": post_view(DID, rkey, indexed_at, json!({
+                    "$type": "app.bsky.feed.post",
+                    "text": "Pörssisähkö",
+                    "tags": tags,
+                    "createdAt": indexed_at,
+                })),
+            })
+        };
+        let path = "/xrpc/app.bsky.feed.getAuthorFeed";
+        // mocks are tried in order, so the second page is set up first
+        let second = server
+            .mock("GET", path)
+            .match_query(Matcher::UrlEncoded(
+                "cursor".to_string(),
+                "page2".to_string(),
+            ))
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "cursor": "page3",
+                    "feed": [feed_post("marked", "2025-01-01T12:00:00.000Z", vec![&marker])],
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let first = server
+            .mock("GET", path)
+            .match_query(Matcher::Any)
+            .expect(1)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "cursor": "page2",
+                    "feed": [
+                        feed_post("other", "2025-01-02T12:00:00.000Z", vec!["sähkö"]),
+                        feed_post("untagged", "2025-01-01T18:00:00.000Z", vec![]),
+                    ],
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let publisher = publisher(&server).await;
+        let existing = publisher
+            .find_existing("10YFI-1--------U", day, "Torstai 2.1.")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            existing.uri,
+            format!("at://{}/app.bsky.feed.post/marked", DID)
+        );
+        assert_eq!(existing.matched_by, "marker");
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+
+    #[test]
+    fn own_replies_are_deleted_deepest_first() {
+        let thread: ThreadViewPost = serde_json::from_value(thread_view(
+            DID,
+            "root",
+            vec![thread_view(
+                DID,
+                "table1",
+                vec![thread_view(DID, "table2", vec![])],
+            )],
+        ))
+        .unwrap();
+        let rkeys: Vec<_> = own_replies(&thread, &DID.parse().unwrap())
+            .into_iter()
+            .map(|uri| uri.rsplit('/').next().unwrap().to_string())
+            .collect();
+        assert_eq!(rkeys, ["table2", "table1"]);
+    }
+
+    #[test]
+    fn day_ahead_record_matches_the_lexicon() {
+        let lexicon: Value =
+            serde_json::from_str(include_str!("../lexicons/app/spotbot/dayAhead.json")).unwrap();
+        let schema = &lexicon["defs"]["main"]["record"];
+        let report = sample_report();
+        let record = compose_day_ahead_record(&report).unwrap();
+
+        assert_eq!(record["$type"], lexicon["id"]);
+        for field in schema["required"].as_array().unwrap() {
+            assert!(record.get(field.as_str().unwrap()).is_some(), "{}", field);
+        }
+        for (field, value) in record.as_object().unwrap() {
+            if field == "$type" {
+                continue;
+            }
+            let property = &schema["properties"][field];
+            let matches = match property["type"].as_str() {
+                Some("string") => value.is_string(),
+                Some("integer") => value.is_i64(),
+                Some("array") => value
+                    .as_array()
+                    .is_some_and(|items| items.iter().all(Value::is_i64)),
+                _ => false,
+            };
+            assert!(matches, "{} = {} for {}", field, value, property);
+        }
+
+        assert_eq!(record["area"], "10YFI-1--------U");
+        assert_eq!(record["areaName"], "FI");
+        assert_eq!(record["deliveryDate"], "2025-01-02");
+        assert_eq!(record["start"], "2025-01-01T22:00:00Z");
+        assert_eq!(record["resolution"], "PT60M");
+        assert_eq!(record["vat"], 2550);
+        let prices = record["prices"].as_array().unwrap();
+        assert_eq!(prices.len(), 24);
+        assert_eq!(prices[0], 2000);
+        assert_eq!(prices[23], 4300);
+    }
+
+    #[test]
+    fn profile_description_fits_the_limit() {
+        let report = sample_report();
+        let max_length = profile::MAX_DESCRIPTION_LENGTH;
+
+        let description = compose_profile_description(Some("Pörssisähkö"), &report);
+        assert!(description.starts_with("Pörssisähkö\n\n"));
+        assert_eq!(
+            description,
+            format!(
+                "Pörssisähkö\n\n{}",
+                report.fit_text(max_length - 13, count_graphemes)
+            )
+        );
+
+        // a description over the limit alone is cut at a grapheme boundary
+        let long = "e\u{301}".repeat(300);
+        let description = compose_profile_description(Some(&long), &report);
+        assert_eq!(count_graphemes(&description), max_length);
+        assert_eq!(
+            description,
+            format!("{}…", "e\u{301}".repeat(max_length - 1))
+        );
+
+        // and one that leaves no room for the prices doesn't end in blank lines
+        let long = "ä".repeat(max_length - 1);
+        assert_eq!(compose_profile_description(Some(&long), &report), long);
+    }
+}
@@ -0,0 +1,347 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use spotbot::config::{Config, Mode};
+use spotbot::{currencies, daemon, entsoe, history, parser, pipeline, publisher};
+
+// The options override the environment variables of the same settings.
+// Without a command everything is read from the environment.
+#[derive(Parser, Debug)]
+#[command(version, about = "Posts day-ahead electricity spot prices")]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Delivery day as YYYY-MM-DD, tomorrow by default
+    #[arg(long, global = true)]
+    date: Option<NaiveDate>,
+
+    /// Bidding zone like FI or SE3, or an EIC area code
+    #[arg(long, global = true)]
+    area: Option<String>,
+
+    /// Locale like fi_FI
+    #[arg(long, global = true)]
+    locale: Option<String>,
+
+    /// Flat VAT rate in percent instead of the country's rules
+    #[arg(long, global = true)]
+    vat: Option<f32>,
+
+    /// Output file; the chart's default name, or stdout for other commands
+    #[arg(long, short, global = true)]
+    output: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Fetch the day's prices from ENTSO-E as XML
+    Fetch,
+    /// Parse ENTSO-E XML into prices in €/MWh as JSON
+    Parse {
+        /// XML file, stdin if not set
+        input: Option<String>,
+    },
+    /// Plot the day's chart
+    Plot {
+        /// Prices from the parse command, fetched from ENTSO-E if not set
+        input: Option<String>,
+    },
+    /// Fetch, plot and post the day's prices
+    Post {
+        /// Print what would be posted instead of posting
+        #[arg(long)]
+        dry_run: bool,
+        /// Re-fetch the prices and correct the posts if they have changed
+        #[arg(long)]
+        verify: bool,
+        /// Post even if the day has already been posted
+        #[arg(long)]
+        force: bool,
+    },
+    /// Keep running and post on each account's schedule
+    Run,
+    /// Store the prices of past days in the price history
+    Backfill {
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to store, today by default
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Fetch days that are already stored
+        #[arg(long)]
+        force: bool,
+    },
+    /// Export prices in €/MWh from the price history
+    Export {
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to export, today by default
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+    },
+    /// Check the configuration and print the settings in use
+    CheckConfig,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Csv,
+    Json,
+}
+
+// prices as written by the parse command
+#[derive(Deserialize)]
+struct PricePoint {
+    time: DateTime<Utc>,
+    price: f32,
+}
+
+impl Cli {
+    // the options as the environment variables they override
+    fn overrides(&self) -> HashMap<String, String> {
+        let mut vars = HashMap::new();
+        if let Some(date) = self.date {
+            vars.insert("SPOTBOT_DATE".to_string(), date.to_string());
+        }
+        if let Some(area) = &self.area {
+            let eic = entsoe::get_eic(area).unwrap_or(area);
+            vars.insert("ENTSOE_EIC".to_string(), eic.to_string());
+        }
+        if let Some(locale) = &self.locale {
+            vars.insert("SPOTBOT_LOCALE".to_string(), locale.clone());
+        }
+        if let Some(vat) = self.vat {
+            vars.insert("VAT".to_string(), vat.to_string());
+        }
+        if let Some(output) = &self.output {
+            vars.insert("SPOTBOT_OUTPUT".to_string(), output.clone());
+        }
+
+        match &self.command {
+            Some(Command::Post {
+                dry_run,
+                verify,
+                force,
+            }) => {
+                let mode = if *dry_run { "dry-run" } else { "post" };
+                vars.insert("SPOTBOT_MODE".to_string(), mode.to_string());
+                vars.insert("SPOTBOT_VERIFY".to_string(), verify.to_string());
+                vars.insert("SPOTBOT_SKIP_EXISTS_CHECK".to_string(), force.to_string());
+            }
+            Some(Command::Run) => {
+                vars.insert("SPOTBOT_MODE".to_string(), "daemon".to_string());
+            }
+            _ => {}
+        }
+        vars
+    }
+}
+
+pub async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let overrides = cli.overrides();
+    let command = match cli.command {
+        None | Some(Command::Run) => return run_env(&overrides).await,
+        Some(command) => command,
+    };
+
+    let config = match Config::from_env(&overrides) {
+        Ok(config) => config,
+        Err(e) if matches!(command, Command::CheckConfig) => {
+            for error in &e.errors {
+                println!("Error: {}", error);
+            }
+            return Err(format!("{} configuration errors", e.errors.len()).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let localization = spotbot::get_localization(config.locale, currencies::get_currencies()?);
+    let area = &config.entsoe_eic;
+    let timezone = &localization.timezone;
+    let today = Utc::now().with_timezone(timezone).date_naive();
+    let day = pipeline::get_day(&config, &localization);
+
+    match command {
+        Command::Fetch => {
+            let xml = entsoe::get_spot_prices(&config.entsoe_apikey, area, day, timezone).await?;
+            write_output(&config, xml.as_bytes())?;
+        }
+        Command::Parse { input } => {
+            let prices = parser::parse_xml(read_input(input.as_deref())?);
+            let json: Vec<_> = prices.iter().map(publisher::price_json).collect();
+            write_output(&config, serde_json::to_string_pretty(&json)?.as_bytes())?;
+        }
+        Command::Plot { input } => {
+            let (day, raw_prices) = match input {
+                Some(path) => {
+                    let points: Vec<PricePoint> = serde_json::from_str(&read_input(Some(&path))?)?;
+                    let raw_prices: Vec<_> = points.iter().map(|p| (p.time, p.price)).collect();
+                    // prices from a file are for the day they start from
+                    let day = config.date.or(raw_prices
+                        .first()
+                        .map(|(ts, _)| ts.with_timezone(timezone).date_naive()));
+                    (day.unwrap_or(today), raw_prices)
+                }
+                None => {
+                    log::info!("Fetching prices from entsoe");
+                    let raw_prices =
+                        spotbot::fetch_day_prices(&config.entsoe_apikey, area, day, timezone)
+                            .await?;
+                    (day, raw_prices)
+                }
+            };
+            pipeline::render_chart(&config, &localization, day, &raw_prices)?;
+        }
+        Command::Post { .. } => pipeline::run(&config).await?,
+        Command::Backfill { from, to, force } => {
+            let mut history = history::History::open(&config.history_db)?;
+            let to = to.unwrap_or(today);
+            let stored: Vec<NaiveDate> = history
+                .list_series(area, from, to)?
+                .iter()
+                .map(|series| series.day)
+                .collect();
+
+            for day in from.iter_days().take_while(|day| *day <= to) {
+                if !force && stored.contains(&day) {
+                    log::info!("{} is already stored, skipping", day);
+                    continue;
+                }
+                let raw_prices =
+                    spotbot::fetch_day_prices(&config.entsoe_apikey, area, day, timezone).await?;
+                if raw_prices.is_empty() {
+                    log::warn!("No prices for {}", day);
+                    continue;
+                }
+                // the current exchange rate is recorded, past rates aren't known
+                let series =
+                    spotbot::get_series_record(area, day, &raw_prices, &localization, &config.vat);
+                history.save_series(&series, &raw_prices)?;
+                log::info!("Stored {} prices for {}", raw_prices.len(), day);
+
+                // be nice to ENTSO-E
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+        Command::Export { from, to, format } => {
+            let history = history::History::open(&config.history_db)?;
+            let mut rows = Vec::new();
+            for series in history.list_series(area, from, to.unwrap_or(today))? {
+                let (start, end) = spotbot::get_day_bounds(series.day, timezone);
+                for (ts, price) in
+                    history.get_prices(area, series.resolution_minutes, start, end)?
+                {
+                    rows.push((series.clone(), ts, price));
+                }
+            }
+
+            let output = match format {
+                Format::Csv => {
+                    let mut csv =
+                        "day,time,resolution,price,vat,currency,exchange_rate\n".to_string();
+                    for (series, ts, price) in &rows {
+                        csv.push_str(&format!(
+                            "{},{},{},{},{},{},{}\n",
+                            series.day,
+                            ts.to_rfc3339(),
+                            series.resolution_minutes,
+                            price,
+                            series.vat,
+                            series.currency_name,
+                            series.exchange_rate
+                        ));
+                    }
+                    csv
+                }
+                Format::Json => {
+                    let json: Vec<_> = rows
+                        .iter()
+                        .map(|(series, ts, price)| {
+                            json!({
+                                "day": series.day,
+                                "time": ts.to_rfc3339(),
+                                "resolution": series.resolution_minutes,
+                                "price": price,
+                                "vat": series.vat,
+                                "currency": series.currency_name,
+                                "exchange_rate": series.exchange_rate,
+                            })
+                        })
+                        .collect();
+                    serde_json::to_string_pretty(&json)?
+                }
+            };
+            write_output(&config, output.as_bytes())?;
+        }
+        Command::CheckConfig => {
+            let (zone, country) = entsoe::get_area(area).unwrap_or(("unknown", "unknown"));
+            let schedule = &config.schedule;
+            println!("Account: {}", config.account);
+            println!("Area: {} ({}), country {}", zone, area, country);
+            println!("Locale: {:?}, timezone {}", config.locale, timezone);
+            println!("Currency: {}", localization.currency_name);
+            println!("VAT on {}: {} %", day, config.vat.rate(day));
+            println!("Mode: {:?}", config.mode);
+            println!("Publishers: {}", config.publishers.join(", "));
+            println!(
+                "Schedule: {}-{} {}, every {} minutes",
+                schedule.start.format("%H:%M"),
+                schedule.end.format("%H:%M"),
+                schedule.timezone,
+                schedule.interval.num_minutes()
+            );
+
+            history::History::open(&config.history_db)?;
+            println!("History: {}", config.history_db);
+            if config.publishers.iter().any(|name| name == "bluesky") {
+                config.bluesky_credentials()?;
+            }
+            // creating the publishers checks their settings without logging in
+            publisher::get_publishers(&config, false).await?;
+            println!("Configuration OK");
+        }
+        Command::Run => unreachable!(),
+    }
+    Ok(())
+}
+
+// daemon accounts from SPOTBOT_ACCOUNTS, or the account in the environment
+async fn run_env(overrides: &HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
+    let accounts = Config::from_account_files(overrides)?;
+    if !accounts.is_empty() {
+        daemon::run(accounts).await;
+        return Ok(());
+    }
+
+    let config = Config::from_env(overrides)?;
+    if config.mode == Mode::Daemon {
+        daemon::run(vec![config]).await;
+        return Ok(());
+    }
+    pipeline::run(&config).await
+}
+
+// file contents, stdin if the path is not set or -
+fn read_input(path: Option<&str>) -> std::io::Result<String> {
+    match path {
+        Some(path) if path != "-" => std::fs::read_to_string(path),
+        _ => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            Ok(input)
+        }
+    }
+}
+
+fn write_output(config: &Config, bytes: &[u8]) -> std::io::Result<()> {
+    match &config.output {
+        Some(path) => std::fs::write(path, bytes),
+        None => std::io::stdout().write_all(bytes),
+    }
+}
@@ -0,0 +1,128 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::imageops::FilterType as ResizeFilter;
+use image::{DynamicImage, ImageEncoder};
+
+// Bluesky's size limit for image blobs
+pub static MAX_IMAGE_BYTES: usize = 1_000_000;
+
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+static JPEG_QUALITIES: [u8; 3] = [90, 80, 70];
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    PngEncoder::new_with_quality(&mut bytes, CompressionType::Best, FilterType::Adaptive)
+        .write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color(),
+        )?;
+    Ok(bytes)
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    // JPEG has no alpha channel
+    let rgb = image.to_rgb8();
+    JpegEncoder::new_with_quality(&mut bytes, quality).write_image(
+        rgb.as_raw(),
+        rgb.width(),
+        rgb.height(),
+        image::ColorType::Rgb8,
+    )?;
+    Ok(bytes)
+}
+
+// Re-encodes the image until it fits within max_bytes: optimized PNG first,
+// then JPEG with decreasing quality, and finally downscaled JPEG.
+pub fn compress(bytes: Vec<u8>, max_bytes: usize) -> Result<Image, Box<dyn std::error::Error>> {
+    let mut image = image::load_from_memory(&bytes)?;
+    let with_dimensions = |bytes: Vec<u8>, image: &DynamicImage| Image {
+        bytes,
+        width: image.width(),
+        height: image.height(),
+    };
+
+    if bytes.len() <= max_bytes {
+        return Ok(with_dimensions(bytes, &image));
+    }
+
+    let png = encode_png(&image)?;
+    if png.len() <= max_bytes {
+        return Ok(with_dimensions(png, &image));
+    }
+
+    loop {
+        for quality in JPEG_QUALITIES {
+            let jpeg = encode_jpeg(&image, quality)?;
+            if jpeg.len() <= max_bytes {
+                return Ok(with_dimensions(jpeg, &image));
+            }
+        }
+        if image.width() < 64 || image.height() < 64 {
+            return Err(format!("Image can't be compressed to {} bytes", max_bytes).into());
+        }
+        image = image.resize(
+            image.width() * 3 / 4,
+            image.height() * 3 / 4,
+            ResizeFilter::Lanczos3,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    // noise doesn't compress well, so only JPEG and downscaling help
+    fn noise(width: u32, height: u32) -> Vec<u8> {
+        let mut state: u32 = 0x2545_f491;
+        let image = RgbImage::from_fn(width, height, |_, _| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let [r, g, b, _] = state.to_le_bytes();
+            image::Rgb([r, g, b])
+        });
+        encode_png(&DynamicImage::ImageRgb8(image)).unwrap()
+    }
+
+    #[test]
+    fn images_within_the_limit_are_kept() {
+        let bytes = noise(40, 20);
+        let image = compress(bytes.clone(), bytes.len()).unwrap();
+        assert_eq!(image.bytes, bytes);
+        assert_eq!((image.width, image.height), (40, 20));
+    }
+
+    #[test]
+    fn large_images_are_downscaled_to_the_limit() {
+        let bytes = noise(400, 200);
+        let max_bytes = 15_000;
+        assert!(bytes.len() > max_bytes);
+
+        let image = compress(bytes, max_bytes).unwrap();
+        assert!(image.bytes.len() <= max_bytes);
+        assert!(image.width < 400);
+        // the aspect ratio is kept within rounding
+        assert!(image.width.abs_diff(image.height * 2) <= 2);
+
+        let decoded = image::load_from_memory(&image.bytes).unwrap();
+        assert_eq!(
+            (decoded.width(), decoded.height()),
+            (image.width, image.height)
+        );
+    }
+
+    #[test]
+    fn impossible_limits_are_errors() {
+        assert!(compress(noise(400, 200), 100).is_err());
+    }
+}
@@ -0,0 +1,474 @@
+use chrono::{Duration, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{entsoe, vat, CheapestQuery, HourRange, Statistic};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // fetch, plot and post
+    Post,
+    // everything up to posting, then print what would have been posted
+    DryRun,
+    // only fetch prices and plot the chart
+    RenderOnly,
+    // update the current price states in MQTT from the price history
+    MqttState,
+    // answer price questions in mentions
+    Reply,
+    // stay running and post on the account's schedule
+    Daemon,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "post" => Ok(Mode::Post),
+            "dry-run" => Ok(Mode::DryRun),
+            "render-only" => Ok(Mode::RenderOnly),
+            "mqtt-state" => Ok(Mode::MqttState),
+            "reply" => Ok(Mode::Reply),
+            "daemon" => Ok(Mode::Daemon),
+            _ => Err(format!("Unknown mode: {}", s)),
+        }
+    }
+}
+
+// how posts are corrected in publishers that can't edit them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CorrectionPolicy {
+    // reply to the post with the corrected report
+    Reply,
+    // delete the post and publish the corrected report
+    Repost,
+}
+
+impl FromStr for CorrectionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reply" => Ok(CorrectionPolicy::Reply),
+            "repost" => Ok(CorrectionPolicy::Repost),
+            _ => Err(format!("Unknown correction policy: {}", s)),
+        }
+    }
+}
+
+// When the daemon tries to post the next day's prices: daily from start,
+// retrying every interval until end, in the timezone
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+    pub interval: Duration,
+    // random delay up to this added to every attempt
+    pub jitter: Duration,
+}
+
+// Account configuration, read from environment variables
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub locale: chrono::Locale,
+    pub entsoe_apikey: String,
+    pub entsoe_eic: String,
+    // name of the account in logs, the post log and file names: the Bluesky
+    // handle, or the area if not posting to Bluesky
+    pub account: String,
+    pub bluesky_username: Option<String>,
+    pub bluesky_password: Option<String>,
+    pub session_file: String,
+    pub history_db: String,
+    pub vat: vat::Vat,
+    pub statistics: Vec<Statistic>,
+    pub peak_hours: HourRange,
+    pub cheapest_window: Option<CheapestQuery>,
+    pub cheapest_slots: Option<CheapestQuery>,
+    pub post_tags: Vec<String>,
+    pub post_mentions: Vec<String>,
+    pub post_link: Option<String>,
+    // reply to the post with every slot's price
+    pub post_price_table: bool,
+    // write the prices as an app.spotbot.dayAhead record next to the post
+    pub post_record: bool,
+    // keep the Bluesky profile description, and optionally banner, up to date
+    pub profile_update: bool,
+    // fixed text before the prices in the profile description
+    pub profile_description: Option<String>,
+    pub profile_banner: bool,
+    pub publishers: Vec<String>,
+    pub mastodon_url: Option<String>,
+    pub mastodon_token: Option<String>,
+    pub telegram_url: Option<String>,
+    pub telegram_token: Option<String>,
+    pub telegram_chat_id: Option<String>,
+    pub matrix_url: Option<String>,
+    pub matrix_token: Option<String>,
+    pub matrix_rooms: Vec<String>,
+    pub webhook_url: Option<String>,
+    pub webhook_template: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_chart_url: Option<String>,
+    pub mqtt_url: Option<String>,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_discovery_prefix: String,
+    pub reply_max_per_run: usize,
+    pub reply_max_per_author: usize,
+    pub mode: Mode,
+    pub skip_exists_check: bool,
+    // re-fetch the prices and correct posts instead of posting
+    pub verify: bool,
+    pub correction_policy: CorrectionPolicy,
+    // largest price change in €/MWh that doesn't need a correction
+    pub correction_threshold: f32,
+    pub schedule: Schedule,
+    // chart file, <account>-<date>.png if not set
+    pub output: Option<String>,
+    // delivery day, tomorrow if not set
+    pub date: Option<NaiveDate>,
+}
+
+// Every missing or invalid setting, so that they can be fixed at once
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.errors.join("\n"))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// settings by name, collecting the errors instead of stopping at the first one
+struct Vars<F: Fn(&str) -> Option<String>> {
+    var: F,
+    errors: RefCell<Vec<String>>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn get(&self, name: &str) -> Option<String> {
+        (self.var)(name)
+    }
+
+    fn fail(&self, error: String) {
+        self.errors.borrow_mut().push(error);
+    }
+
+    fn required(&self, name: &str) -> String {
+        self.get(name).unwrap_or_else(|| {
+            self.fail(format!("{} must be set", name));
+            String::new()
+        })
+    }
+
+    // None if not set or invalid
+    fn parse<T: FromStr>(&self, name: &str, expected: &str) -> Option<T> {
+        let value = self.get(name)?;
+        match value.trim().parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.fail(format!("{} must be {}, not {:?}", name, expected, value));
+                None
+            }
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|s| s == "1" || s == "true")
+    }
+
+    fn list(&self, name: &str) -> Vec<String> {
+        self.get(name)
+            .map(|s| {
+                s.split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn time(&self, name: &str, default: NaiveTime) -> NaiveTime {
+        match self.get(name) {
+            Some(value) => NaiveTime::parse_from_str(value.trim(), "%H:%M").unwrap_or_else(|_| {
+                self.fail(format!("{} must be HH:MM, not {:?}", name, value));
+                default
+            }),
+            None => default,
+        }
+    }
+}
+
+impl Config {
+    // environment variables, with overrides from the command line
+    pub fn from_env(overrides: &HashMap<String, String>) -> Result<Config, ConfigError> {
+        Config::from_vars(|name| overrides.get(name).cloned().or_else(|| env::var(name).ok()))
+    }
+
+    // daemon accounts, each from an env file listed in SPOTBOT_ACCOUNTS, with
+    // the environment as defaults for settings shared by all accounts
+    pub fn from_account_files(
+        overrides: &HashMap<String, String>,
+    ) -> Result<Vec<Config>, ConfigError> {
+        let mut accounts = Vec::new();
+        let mut errors = Vec::new();
+        let paths = env::var("SPOTBOT_ACCOUNTS").unwrap_or_default();
+        for path in paths.split(',').map(|path| path.trim()) {
+            if path.is_empty() {
+                continue;
+            }
+            // the file is read without loading it into the environment
+            #[allow(deprecated)]
+            let vars: Result<HashMap<String, String>, _> =
+                dotenv::from_filename_iter(path).and_then(|items| items.collect());
+            let vars = match vars {
+                Ok(vars) => vars,
+                Err(e) => {
+                    errors.push(format!("Can't read account file {}: {}", path, e));
+                    continue;
+                }
+            };
+            let config = Config::from_vars(|name| {
+                overrides
+                    .get(name)
+                    .or(vars.get(name))
+                    .cloned()
+                    .or_else(|| env::var(name).ok())
+            });
+            match config {
+                Ok(config) => accounts.push(config),
+                Err(e) => errors.extend(e.errors.iter().map(|e| format!("{}: {}", path, e))),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(accounts)
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let vars = Vars {
+            var,
+            errors: RefCell::new(Vec::new()),
+        };
+
+        let locale = match vars.get("SPOTBOT_LOCALE") {
+            Some(_) => vars.parse("SPOTBOT_LOCALE", "a locale like fi_FI"),
+            None => {
+                vars.required("SPOTBOT_LOCALE");
+                None
+            }
+        };
+        let entsoe_eic = vars.required("ENTSOE_EIC");
+        let bluesky_username = vars.get("BLUESKY_USERNAME");
+        let account = bluesky_username.clone().unwrap_or(entsoe_eic.clone());
+
+        let country = vars
+            .get("VAT_COUNTRY")
+            .or_else(|| entsoe::get_area(&entsoe_eic).map(|(_, country)| country.to_string()));
+        let negative_prices = vars
+            .parse("VAT_NEGATIVE_PRICES", "apply or exempt")
+            .unwrap_or(vat::NegativePricePolicy::default_for(
+                country.as_deref().unwrap_or_default(),
+            ));
+        let vat = match vars.get("VAT") {
+            // legacy flat rate overrides the country rules
+            Some(_) => vat::Vat::flat(
+                vars.parse("VAT", "a number").unwrap_or_default(),
+                negative_prices,
+            ),
+            None => {
+                match country.and_then(|country| vat::Vat::for_country(&country, negative_prices)) {
+                    Some(vat) => vat,
+                    None => {
+                        vars.fail(
+                            "VAT_COUNTRY must be a known country, or VAT a flat rate, \
+                         when ENTSOE_EIC isn't a known area"
+                                .to_string(),
+                        );
+                        vat::Vat::flat(0.0, negative_prices)
+                    }
+                }
+            }
+        };
+
+        let statistics = vars
+            .get("POST_STATISTICS")
+            .unwrap_or("avg,min,max,vs_today,vs_last_week,vs_30d".to_string())
+            .split(',')
+            .filter_map(|s| match s.parse() {
+                Ok(statistic) => Some(statistic),
+                Err(e) => {
+                    vars.fail(format!("Invalid POST_STATISTICS: {}", e));
+                    None
+                }
+            })
+            .collect();
+        let publishers = match vars.list("SPOTBOT_PUBLISHERS") {
+            publishers if publishers.is_empty() => vec!["bluesky".to_string()],
+            publishers => publishers,
+        };
+
+        let config = Config {
+            locale: locale.unwrap_or(chrono::Locale::POSIX),
+            entsoe_apikey: vars.required("ENTSOE_APIKEY"),
+            bluesky_password: vars.get("BLUESKY_PASSWORD"),
+            session_file: vars
+                .get("BLUESKY_SESSION_FILE")
+                .unwrap_or(format!("{}.session.json", account)),
+            history_db: vars
+                .get("HISTORY_DB")
+                .unwrap_or("spotbot.sqlite".to_string()),
+            vat,
+            statistics,
+            peak_hours: vars
+                .parse("PEAK_HOURS", "local hours like 8-20")
+                .unwrap_or(HourRange { from: 8, to: 20 }),
+            cheapest_window: vars.parse("CHEAPEST_WINDOW", "a length like 3h or 90m@22-07"),
+            cheapest_slots: vars.parse("CHEAPEST_SLOTS", "a length like 3h or 90m@22-07"),
+            post_tags: vars.list("POST_TAGS"),
+            post_mentions: vars.list("POST_MENTIONS"),
+            post_link: vars.get("POST_LINK"),
+            post_price_table: vars.flag("POST_PRICE_TABLE"),
+            post_record: vars.flag("POST_RECORD"),
+            profile_update: vars.flag("PROFILE_UPDATE"),
+            profile_description: vars.get("PROFILE_DESCRIPTION"),
+            profile_banner: vars.flag("PROFILE_BANNER"),
+            publishers,
+            mastodon_url: vars.get("MASTODON_URL"),
+            mastodon_token: vars.get("MASTODON_TOKEN"),
+            telegram_url: vars.get("TELEGRAM_URL"),
+            telegram_token: vars.get("TELEGRAM_TOKEN"),
+            telegram_chat_id: vars.get("TELEGRAM_CHAT_ID"),
+            matrix_url: vars.get("MATRIX_URL"),
+            matrix_token: vars.get("MATRIX_TOKEN"),
+            matrix_rooms: vars.list("MATRIX_ROOMS"),
+            webhook_url: vars.get("WEBHOOK_URL"),
+            webhook_template: vars.get("WEBHOOK_TEMPLATE"),
+            webhook_secret: vars.get("WEBHOOK_SECRET"),
+            webhook_chart_url: vars.get("WEBHOOK_CHART_URL"),
+            mqtt_url: vars.get("MQTT_URL"),
+            mqtt_topic_prefix: vars
+                .get("MQTT_TOPIC_PREFIX")
+                .unwrap_or("spotbot".to_string()),
+            mqtt_discovery_prefix: vars
+                .get("MQTT_DISCOVERY_PREFIX")
+                .unwrap_or("homeassistant".to_string()),
+            reply_max_per_run: vars.parse("REPLY_MAX_PER_RUN", "a number").unwrap_or(10),
+            reply_max_per_author: vars.parse("REPLY_MAX_PER_AUTHOR", "a number").unwrap_or(3),
+            mode: vars
+                .parse(
+                    "SPOTBOT_MODE",
+                    "post, dry-run, render-only, mqtt-state, reply or daemon",
+                )
+                .unwrap_or(Mode::Post),
+            skip_exists_check: vars.flag("SPOTBOT_SKIP_EXISTS_CHECK"),
+            verify: vars.flag("SPOTBOT_VERIFY"),
+            correction_policy: vars
+                .parse("CORRECTION_POLICY", "reply or repost")
+                .unwrap_or(CorrectionPolicy::Reply),
+            correction_threshold: vars
+                .parse("CORRECTION_THRESHOLD", "a number")
+                .unwrap_or(1.0),
+            schedule: Schedule {
+                start: vars.time(
+                    "SCHEDULE_START",
+                    NaiveTime::from_hms_opt(12, 45, 0).unwrap(),
+                ),
+                end: vars.time("SCHEDULE_END", NaiveTime::from_hms_opt(18, 0, 0).unwrap()),
+                timezone: vars
+                    .parse("SCHEDULE_TIMEZONE", "a timezone like Europe/Helsinki")
+                    .unwrap_or(chrono_tz::Europe::Helsinki),
+                interval: Duration::minutes(
+                    vars.parse("SCHEDULE_INTERVAL", "minutes").unwrap_or(10),
+                ),
+                jitter: Duration::seconds(vars.parse("SCHEDULE_JITTER", "seconds").unwrap_or(120)),
+            },
+            output: vars.get("SPOTBOT_OUTPUT"),
+            date: vars.parse("SPOTBOT_DATE", "YYYY-MM-DD"),
+            entsoe_eic,
+            account,
+            bluesky_username,
+        };
+
+        match vars.errors.into_inner() {
+            errors if errors.is_empty() => Ok(config),
+            errors => Err(ConfigError { errors }),
+        }
+    }
+
+    pub fn bluesky_credentials(&self) -> Result<(&str, &str), &'static str> {
+        match (&self.bluesky_username, &self.bluesky_password) {
+            (Some(username), Some(password)) => Ok((username, password)),
+            _ => Err("BLUESKY_USERNAME and BLUESKY_PASSWORD must be set for Bluesky"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // a Finnish account with the settings added or overridden
+    pub fn config(settings: &[(&str, &str)]) -> Config {
+        let mut all = vec![
+            ("SPOTBOT_LOCALE", "fi_FI"),
+            ("ENTSOE_EIC", "10YFI-1--------U"),
+            ("ENTSOE_APIKEY", "key"),
+        ];
+        all.extend(settings);
+        Config::from_vars(vars(&all)).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn valid_settings() {
+        let config = Config::from_vars(vars(&[
+            ("SPOTBOT_LOCALE", "fi_FI"),
+            ("ENTSOE_EIC", "10YFI-1--------U"),
+            ("ENTSOE_APIKEY", "key"),
+            ("SCHEDULE_START", "13:15"),
+            ("PEAK_HOURS", "7-22"),
+        ]))
+        .unwrap();
+        assert_eq!(config.account, "10YFI-1--------U");
+        assert_eq!(config.mode, Mode::Post);
+        assert_eq!(
+            config.schedule.start,
+            NaiveTime::from_hms_opt(13, 15, 0).unwrap()
+        );
+        assert_eq!((config.peak_hours.from, config.peak_hours.to), (7, 22));
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let error = Config::from_vars(vars(&[
+            ("SPOTBOT_LOCALE", "fi_FI"),
+            ("ENTSOE_EIC", "10YFI-1--------U"),
+            ("SPOTBOT_MODE", "nope"),
+            ("SCHEDULE_INTERVAL", "ten"),
+        ]))
+        .unwrap_err();
+        assert_eq!(error.errors.len(), 3);
+        assert!(error.errors[0].contains("ENTSOE_APIKEY"));
+    }
+}
@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
 use std::fs::File;
 use std::io::BufReader;
 
@@ -33,12 +32,17 @@ pub struct Currencies {
     pub sek_ore: f32,
 }
 
-pub fn get_currencies() -> Result<Currencies> {
-    // Open the JSON file
-    let file = File::open("exchange-rates.json").expect("File not found");
+/// exchange rates from exchange-rates.json in the working directory
+pub fn get_currencies() -> Result<Currencies, Box<dyn std::error::Error>> {
+    read_currencies("exchange-rates.json")
+}
+
+fn read_currencies(path: &str) -> Result<Currencies, Box<dyn std::error::Error>> {
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
     let reader = BufReader::new(file);
 
-    let exchange_rate: ExchangeRate = serde_json::from_reader(reader)?;
+    let exchange_rate: ExchangeRate =
+        serde_json::from_reader(reader).map_err(|e| format!("Invalid {}: {}", path, e))?;
 
     let rates = Currencies {
         eur_c: 100.0,
@@ -49,3 +53,35 @@ pub fn get_currencies() -> Result<Currencies> {
 
     Ok(rates)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_rates_are_errors() {
+        let error = read_currencies("no-such-exchange-rates.json").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Could not open no-such-exchange-rates.json"));
+    }
+
+    #[test]
+    fn rates_are_read() {
+        let path = std::env::temp_dir().join(format!("spotbot-rates-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            r#"{"data": {"DKK": {"code": "DKK", "value": 7.46},
+                "NOK": {"code": "NOK", "value": 11.7},
+                "SEK": {"code": "SEK", "value": 11.5}}}"#,
+        )
+        .unwrap();
+        let currencies = read_currencies(path);
+        std::fs::remove_file(path).unwrap();
+
+        let currencies = currencies.unwrap();
+        assert_eq!(currencies.dkk, 7.46);
+        assert_eq!(currencies.sek_ore, 1150.0);
+    }
+}
@@ -0,0 +1,298 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Offset, TimeZone, Utc};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use tokio::sync::watch;
+
+use crate::config::{Config, Mode, Schedule};
+
+// longest single sleep, so that wall clock jumps like host suspend are noticed
+static MAX_SLEEP_SECONDS: i64 = 60;
+
+// Runs every account on its schedule until SIGTERM or Ctrl-C. Runs already
+// in progress are finished before stopping.
+pub async fn run(accounts: Vec<Config>) {
+    let (stop, stopped) = watch::channel(false);
+
+    // publishers aren't Send, so all accounts run on this thread
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async move {
+            let handles: Vec<_> = accounts
+                .into_iter()
+                .map(|config| tokio::task::spawn_local(run_account(config, stopped.clone())))
+                .collect();
+
+            tokio::task::spawn_local(async move {
+                wait_for_signal().await;
+                log::info!("Shutting down after runs in progress");
+                let _ = stop.send(true);
+            });
+
+            for handle in handles {
+                if let Err(e) = handle.await {
+                    log::error!("Account stopped unexpectedly: {}", e);
+                }
+            }
+        })
+        .await;
+}
+
+async fn run_account(mut config: Config, mut stopped: watch::Receiver<bool>) {
+    if config.mode == Mode::Daemon {
+        config.mode = Mode::Post;
+    }
+    let name = config.account.clone();
+    let schedule = config.schedule.clone();
+
+    // latest delivery day that has been handled
+    let mut done: Option<NaiveDate> = None;
+    let mut retry_at: Option<DateTime<Utc>> = None;
+    // a day missed while the daemon was down is tried right away
+    let mut catch_up = true;
+
+    loop {
+        let (at, day) = next_attempt(&schedule, Utc::now(), done, retry_at, catch_up);
+        let at = at + random_jitter(schedule.jitter);
+        catch_up = false;
+
+        log::info!(
+            "{}: next run for {} at {}",
+            name,
+            day,
+            at.with_timezone(&schedule.timezone)
+        );
+        if !sleep_until(at, &mut stopped).await {
+            break;
+        }
+
+        config.date = Some(day);
+        match crate::pipeline::run(&config).await {
+            Ok(()) => {
+                done = Some(day);
+                retry_at = None;
+            }
+            Err(e) => {
+                log::warn!("{}: run for {} failed: {}", name, day, e);
+                retry_at = Some(Utc::now() + schedule.interval);
+            }
+        }
+    }
+
+    log::info!("{}: stopped", name);
+}
+
+// When to run next and for which delivery day. Tomorrow's prices are tried
+// from the start of today's window until its end, after that the next try is
+// in tomorrow's window. Catching up ignores the end of the window.
+fn next_attempt(
+    schedule: &Schedule,
+    now: DateTime<Utc>,
+    done: Option<NaiveDate>,
+    retry_at: Option<DateTime<Utc>>,
+    catch_up: bool,
+) -> (DateTime<Utc>, NaiveDate) {
+    let today = now.with_timezone(&schedule.timezone).date_naive();
+    let tomorrow = today + Duration::days(1);
+    let start = schedule_time(schedule, today, schedule.start);
+    let end = schedule_time(schedule, today, schedule.end);
+
+    if done != Some(tomorrow) {
+        if catch_up && now >= start {
+            return (now, tomorrow);
+        }
+        let at = retry_at.unwrap_or(now).max(start);
+        if at < end {
+            return (at, tomorrow);
+        }
+    }
+
+    (
+        schedule_time(schedule, tomorrow, schedule.start),
+        tomorrow + Duration::days(1),
+    )
+}
+
+// A time skipped by a DST change is moved forward by the length of the gap,
+// and the first of repeated times is used.
+fn schedule_time(schedule: &Schedule, day: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = day.and_time(time);
+    match schedule.timezone.from_local_datetime(&local).earliest() {
+        Some(ts) => ts.with_timezone(&Utc),
+        None => {
+            // the offset from before the change
+            let offset = schedule
+                .timezone
+                .offset_from_utc_datetime(&(local - Duration::days(1)))
+                .fix();
+            Utc.from_utc_datetime(&(local - Duration::seconds(offset.local_minus_utc().into())))
+        }
+    }
+}
+
+// spreads the requests of several accounts and instances over time
+fn random_jitter(max: Duration) -> Duration {
+    let max = max.num_milliseconds();
+    if max <= 0 {
+        return Duration::zero();
+    }
+    // RandomState is randomly seeded, which is enough for jitter
+    let random = RandomState::new().build_hasher().finish();
+    Duration::milliseconds((random % max as u64) as i64)
+}
+
+// false if stopped before the time
+async fn sleep_until(at: DateTime<Utc>, stopped: &mut watch::Receiver<bool>) -> bool {
+    loop {
+        if *stopped.borrow() {
+            return false;
+        }
+        let remaining = at - Utc::now();
+        if remaining <= Duration::zero() {
+            return true;
+        }
+        let duration = remaining
+            .min(Duration::seconds(MAX_SLEEP_SECONDS))
+            .to_std()
+            .unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = stopped.changed() => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Can't listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> Schedule {
+        Schedule {
+            start: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            timezone: chrono_tz::Europe::Helsinki,
+            interval: Duration::minutes(10),
+            jitter: Duration::seconds(120),
+        }
+    }
+
+    fn local(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        chrono_tz::Europe::Helsinki
+            .with_ymd_and_hms(2025, 1, day, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 1, day).unwrap()
+    }
+
+    #[test]
+    fn waits_for_the_window() {
+        let schedule = schedule();
+        assert_eq!(
+            next_attempt(&schedule, local(2, 9, 0), None, None, true),
+            (local(2, 13, 0), day(3))
+        );
+        assert_eq!(
+            next_attempt(&schedule, local(2, 14, 0), None, None, false),
+            (local(2, 14, 0), day(3))
+        );
+        // tomorrow is already done
+        assert_eq!(
+            next_attempt(&schedule, local(2, 14, 0), Some(day(3)), None, false),
+            (local(3, 13, 0), day(4))
+        );
+    }
+
+    #[test]
+    fn retries_until_the_window_ends() {
+        let schedule = schedule();
+        assert_eq!(
+            next_attempt(
+                &schedule,
+                local(2, 14, 0),
+                None,
+                Some(local(2, 14, 10)),
+                false
+            ),
+            (local(2, 14, 10), day(3))
+        );
+        assert_eq!(
+            next_attempt(
+                &schedule,
+                local(2, 17, 55),
+                None,
+                Some(local(2, 18, 5)),
+                false
+            ),
+            (local(3, 13, 0), day(4))
+        );
+    }
+
+    #[test]
+    fn catches_up_after_the_window() {
+        let schedule = schedule();
+        assert_eq!(
+            next_attempt(&schedule, local(2, 23, 0), None, None, true),
+            (local(2, 23, 0), day(3))
+        );
+        assert_eq!(
+            next_attempt(&schedule, local(2, 23, 0), None, None, false),
+            (local(3, 13, 0), day(4))
+        );
+        assert_eq!(
+            next_attempt(&schedule, local(2, 23, 0), Some(day(3)), None, true),
+            (local(3, 13, 0), day(4))
+        );
+    }
+
+    #[test]
+    fn schedule_times_over_dst_changes() {
+        let schedule = schedule();
+        let time = NaiveTime::from_hms_opt(3, 30, 0).unwrap();
+        // 03:30 is skipped in the spring and becomes 04:30 summer time
+        assert_eq!(
+            schedule_time(
+                &schedule,
+                NaiveDate::from_ymd_opt(2025, 3, 30).unwrap(),
+                time
+            ),
+            Utc.with_ymd_and_hms(2025, 3, 30, 1, 30, 0).unwrap()
+        );
+        // and repeated in the autumn
+        assert_eq!(
+            schedule_time(
+                &schedule,
+                NaiveDate::from_ymd_opt(2025, 10, 26).unwrap(),
+                time
+            ),
+            Utc.with_ymd_and_hms(2025, 10, 26, 0, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn jitter_stays_below_the_maximum() {
+        assert_eq!(random_jitter(Duration::zero()), Duration::zero());
+        assert_eq!(random_jitter(Duration::seconds(-1)), Duration::zero());
+        for _ in 0..100 {
+            let jitter = random_jitter(Duration::seconds(2));
+            assert!(jitter >= Duration::zero() && jitter < Duration::seconds(2));
+        }
+    }
+}
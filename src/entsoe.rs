@@ -3,7 +3,12 @@ use chrono_tz::Tz;
 
 static ENTSOE_URL: &str = "https://web-api.tp.entsoe.eu/api";
 
-pub async fn get_spot_prices(apikey: &str, eic: &str, day: NaiveDate, timezone: &Tz) -> String {
+pub async fn get_spot_prices(
+    apikey: &str,
+    eic: &str,
+    day: NaiveDate,
+    timezone: &Tz,
+) -> Result<String, reqwest::Error> {
     let start_of_day = day.and_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap());
     let start = timezone.from_local_datetime(&start_of_day).unwrap();
 
@@ -20,6 +25,50 @@ pub async fn get_spot_prices(apikey: &str, eic: &str, day: NaiveDate, timezone:
         ("securityToken", apikey.to_string()),
     ];
     let client = reqwest::Client::new();
-    let res = client.get(ENTSOE_URL).query(&params).send().await.unwrap();
-    res.text().await.unwrap()
+    // the URL has the API key, keep it out of the errors
+    let res = client
+        .get(ENTSOE_URL)
+        .query(&params)
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    res.error_for_status()
+        .map_err(|e| e.without_url())?
+        .text()
+        .await
+}
+
+// EIC area code, bidding zone code and country code of the supported areas
+static AREAS: [(&str, &str, &str); 15] = [
+    ("10YFI-1--------U", "FI", "FI"),
+    ("10Y1001A1001A39I", "EE", "EE"),
+    ("10YLV-1001A00074", "LV", "LV"),
+    ("10YLT-1001A0008Q", "LT", "LT"),
+    ("10Y1001A1001A44P", "SE1", "SE"),
+    ("10Y1001A1001A45N", "SE2", "SE"),
+    ("10Y1001A1001A46L", "SE3", "SE"),
+    ("10Y1001A1001A47J", "SE4", "SE"),
+    ("10YDK-1--------W", "DK1", "DK"),
+    ("10YDK-2--------M", "DK2", "DK"),
+    ("10YNO-1--------2", "NO1", "NO"),
+    ("10YNO-2--------T", "NO2", "NO"),
+    ("10YNO-3--------J", "NO3", "NO"),
+    ("10YNO-4--------9", "NO4", "NO"),
+    ("10Y1001A1001A48H", "NO5", "NO"),
+];
+
+// bidding zone code and country code for an EIC area code
+pub fn get_area(eic: &str) -> Option<(&'static str, &'static str)> {
+    AREAS
+        .iter()
+        .find(|(code, _, _)| *code == eic)
+        .map(|(_, zone, country)| (*zone, *country))
+}
+
+// EIC area code for a bidding zone code like SE3
+pub fn get_eic(zone: &str) -> Option<&'static str> {
+    AREAS
+        .iter()
+        .find(|(_, code, _)| code.eq_ignore_ascii_case(zone))
+        .map(|(eic, _, _)| *eic)
 }
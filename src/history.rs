@@ -0,0 +1,411 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+
+// Local price history. Prices are stored as published by ENTSO-E in €/MWh,
+// along with the conversion used for each day and the resulting posts.
+pub struct History {
+    conn: Connection,
+}
+
+// a day's price series and the values used to convert it for posting
+#[derive(Clone, Debug)]
+pub struct SeriesRecord {
+    pub area: String,
+    pub day: NaiveDate,
+    pub resolution_minutes: i64,
+    pub vat: f32,
+    pub currency_name: String,
+    // multiplier from EUR to the local currency unit, e.g. 100 for cents
+    pub exchange_rate: f32,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PostRecord {
+    pub account: String,
+    pub area: String,
+    pub day: NaiveDate,
+    pub publisher: String,
+    pub uri: String,
+    pub posted_at: DateTime<Utc>,
+    // revision of the prices shown in the post, None in posts logged before
+    // revisions were recorded
+    pub revision: Option<String>,
+}
+
+// a mention or reply the bot has handled, reply_uri is None if it was left unanswered
+#[derive(Clone, Debug)]
+pub struct ReplyRecord {
+    pub account: String,
+    pub notification_uri: String,
+    pub author: String,
+    pub reply_uri: Option<String>,
+    pub replied_at: DateTime<Utc>,
+    // when Bluesky indexed the notification
+    pub indexed_at: DateTime<Utc>,
+}
+
+// the timestamp in the column, an error if it's out of range
+fn timestamp_at(row: &Row, index: usize) -> Result<DateTime<Utc>> {
+    let ts: i64 = row.get(index)?;
+    DateTime::from_timestamp(ts, 0).ok_or(rusqlite::Error::IntegralValueOutOfRange(index, ts))
+}
+
+fn series_from_row(row: &Row) -> Result<SeriesRecord> {
+    Ok(SeriesRecord {
+        area: row.get(0)?,
+        day: row.get(1)?,
+        resolution_minutes: row.get(2)?,
+        vat: row.get(3)?,
+        currency_name: row.get(4)?,
+        exchange_rate: row.get(5)?,
+        fetched_at: timestamp_at(row, 6)?,
+    })
+}
+
+fn post_from_row(row: &Row) -> Result<PostRecord> {
+    Ok(PostRecord {
+        account: row.get(0)?,
+        area: row.get(1)?,
+        day: row.get(2)?,
+        publisher: row.get(3)?,
+        uri: row.get(4)?,
+        posted_at: timestamp_at(row, 5)?,
+        revision: row.get(6)?,
+    })
+}
+
+// schema migrations, the index + 1 is stored as the user_version of the database
+static MIGRATIONS: &[&str] = &[
+    "CREATE TABLE prices (
+        area TEXT NOT NULL,
+        resolution INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        price REAL NOT NULL,
+        PRIMARY KEY (area, resolution, timestamp)
+    )",
+    "CREATE TABLE series (
+        area TEXT NOT NULL,
+        day TEXT NOT NULL,
+        resolution INTEGER NOT NULL,
+        vat REAL NOT NULL,
+        currency_name TEXT NOT NULL,
+        exchange_rate REAL NOT NULL,
+        fetched_at INTEGER NOT NULL,
+        PRIMARY KEY (area, day, resolution)
+    );
+    CREATE TABLE posts (
+        account TEXT NOT NULL,
+        area TEXT NOT NULL,
+        day TEXT NOT NULL,
+        publisher TEXT NOT NULL,
+        uri TEXT NOT NULL,
+        posted_at INTEGER NOT NULL
+    );
+    CREATE UNIQUE INDEX posts_day ON posts (account, area, day, publisher);",
+    "CREATE TABLE replies (
+        account TEXT NOT NULL,
+        notification_uri TEXT NOT NULL,
+        author TEXT NOT NULL,
+        reply_uri TEXT,
+        replied_at INTEGER NOT NULL,
+        PRIMARY KEY (account, notification_uri)
+    );
+    CREATE INDEX replies_author ON replies (account, author, replied_at);",
+    "ALTER TABLE posts ADD COLUMN revision TEXT",
+    "ALTER TABLE replies ADD COLUMN indexed_at INTEGER",
+];
+
+impl History {
+    pub fn open(path: &str) -> Result<History> {
+        let mut conn = Connection::open(path)?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", (), |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+
+        Ok(History { conn })
+    }
+
+    // Changes from here on are rolled back when the history is closed, so
+    // that dry runs can do everything a real run does
+    pub fn discard_changes(&mut self) -> Result<()> {
+        self.conn.execute_batch("BEGIN")
+    }
+
+    pub fn save_prices(
+        &mut self,
+        area: &str,
+        resolution_minutes: i64,
+        prices: &[(DateTime<Utc>, f32)],
+    ) -> Result<()> {
+        // a savepoint nests within the transaction of discard_changes
+        let tx = self.conn.savepoint()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO prices (area, resolution, timestamp, price)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (ts, price) in prices {
+                stmt.execute(params![area, resolution_minutes, ts.timestamp(), price])?;
+            }
+        }
+        tx.commit()
+    }
+
+    // prices from start (inclusive) to end (exclusive) in chronological order
+    pub fn get_prices(
+        &self,
+        area: &str,
+        resolution_minutes: i64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, f32)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, price FROM prices
+             WHERE area = ?1 AND resolution = ?2 AND timestamp >= ?3 AND timestamp < ?4
+             ORDER BY timestamp",
+        )?;
+        let rows = stmt.query_map(
+            params![area, resolution_minutes, start.timestamp(), end.timestamp()],
+            |row| Ok((timestamp_at(row, 0)?, row.get(1)?)),
+        )?;
+        rows.collect()
+    }
+
+    // stores the day's prices and the conversion used for them
+    pub fn save_series(
+        &mut self,
+        series: &SeriesRecord,
+        prices: &[(DateTime<Utc>, f32)],
+    ) -> Result<()> {
+        self.save_prices(&series.area, series.resolution_minutes, prices)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO series
+             (area, day, resolution, vat, currency_name, exchange_rate, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                series.area,
+                series.day,
+                series.resolution_minutes,
+                series.vat,
+                series.currency_name,
+                series.exchange_rate,
+                series.fetched_at.timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_series(
+        &self,
+        area: &str,
+        day: NaiveDate,
+        resolution_minutes: i64,
+    ) -> Result<Option<SeriesRecord>> {
+        self.conn
+            .query_row(
+                "SELECT area, day, resolution, vat, currency_name, exchange_rate, fetched_at
+                 FROM series WHERE area = ?1 AND day = ?2 AND resolution = ?3",
+                params![area, day, resolution_minutes],
+                series_from_row,
+            )
+            .optional()
+    }
+
+    // stored series of the area between the days, inclusive, by day and resolution
+    pub fn list_series(
+        &self,
+        area: &str,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<SeriesRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT area, day, resolution, vat, currency_name, exchange_rate, fetched_at
+             FROM series WHERE area = ?1 AND day >= ?2 AND day <= ?3
+             ORDER BY day, resolution",
+        )?;
+        let rows = stmt.query_map(params![area, start, end], series_from_row)?;
+        rows.collect()
+    }
+
+    // a publisher's later post of the day, e.g. a correction, replaces the earlier one
+    pub fn save_post(&self, post: &PostRecord) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO posts
+             (account, area, day, publisher, uri, posted_at, revision)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                post.account,
+                post.area,
+                post.day,
+                post.publisher,
+                post.uri,
+                post.posted_at.timestamp(),
+                post.revision
+            ],
+        )?;
+        Ok(())
+    }
+
+    // posts of the account for the area and day, latest first
+    pub fn get_posts(&self, account: &str, area: &str, day: NaiveDate) -> Result<Vec<PostRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT account, area, day, publisher, uri, posted_at, revision
+             FROM posts WHERE account = ?1 AND area = ?2 AND day = ?3
+             ORDER BY posted_at DESC",
+        )?;
+        let rows = stmt.query_map(params![account, area, day], post_from_row)?;
+        rows.collect()
+    }
+
+    pub fn has_reply(&self, account: &str, notification_uri: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM replies WHERE account = ?1 AND notification_uri = ?2)",
+            params![account, notification_uri],
+            |row| row.get(0),
+        )
+    }
+
+    // replies sent to the author since the given time
+    pub fn count_replies(
+        &self,
+        account: &str,
+        author: &str,
+        since: DateTime<Utc>,
+    ) -> Result<usize> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM replies
+             WHERE account = ?1 AND author = ?2 AND replied_at >= ?3 AND reply_uri IS NOT NULL",
+            params![account, author, since.timestamp()],
+            |row| row.get(0),
+        )
+    }
+
+    // indexing time of the latest handled notification of the account
+    pub fn last_notification_at(&self, account: &str) -> Result<Option<DateTime<Utc>>> {
+        let ts: Option<i64> = self.conn.query_row(
+            "SELECT MAX(indexed_at) FROM replies WHERE account = ?1",
+            params![account],
+            |row| row.get(0),
+        )?;
+        Ok(ts.and_then(|ts| DateTime::from_timestamp(ts, 0)))
+    }
+
+    pub fn save_reply(&self, reply: &ReplyRecord) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO replies
+             (account, notification_uri, author, reply_uri, replied_at, indexed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                reply.account,
+                reply.notification_uri,
+                reply.author,
+                reply.reply_uri,
+                reply.replied_at.timestamp(),
+                reply.indexed_at.timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn series(day: NaiveDate) -> SeriesRecord {
+        SeriesRecord {
+            area: "10YFI-1--------U".to_string(),
+            day,
+            resolution_minutes: 60,
+            vat: 25.5,
+            currency_name: "c".to_string(),
+            exchange_rate: 100.0,
+            fetched_at: Utc::now(),
+        }
+    }
+
+    fn prices() -> Vec<(DateTime<Utc>, f32)> {
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 22, 0, 0).unwrap();
+        (0..24)
+            .map(|i| (start + Duration::hours(i), i as f32))
+            .collect()
+    }
+
+    #[test]
+    fn discarded_changes_are_not_saved() {
+        let path = std::env::temp_dir().join(format!("spotbot-test-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        let area = "10YFI-1--------U";
+
+        let mut history = History::open(path).unwrap();
+        history.save_series(&series(day), &prices()).unwrap();
+        history.discard_changes().unwrap();
+        history
+            .save_series(&series(day + Duration::days(1)), &prices())
+            .unwrap();
+        // visible until the history is closed
+        assert_eq!(
+            history
+                .list_series(area, day, day + Duration::days(1))
+                .unwrap()
+                .len(),
+            2
+        );
+        drop(history);
+
+        let history = History::open(path).unwrap();
+        let stored = history
+            .list_series(area, day, day + Duration::days(1))
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].day, day);
+    }
+
+    fn post(uri: &str) -> PostRecord {
+        PostRecord {
+            account: "spotbot.bsky.social".to_string(),
+            area: "10YFI-1--------U".to_string(),
+            day: NaiveDate::from_ymd_opt(2025, 1, 2).unwrap(),
+            publisher: "bluesky".to_string(),
+            uri: uri.to_string(),
+            posted_at: Utc::now(),
+            revision: None,
+        }
+    }
+
+    #[test]
+    fn one_post_per_publisher_and_day() {
+        let history = History::open(":memory:").unwrap();
+        history.save_post(&post("at://post/1")).unwrap();
+        history.save_post(&post("at://post/2")).unwrap();
+
+        let posts = history
+            .get_posts("spotbot.bsky.social", "10YFI-1--------U", post("").day)
+            .unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].uri, "at://post/2");
+    }
+
+    #[test]
+    fn invalid_timestamps_are_errors() {
+        let history = History::open(":memory:").unwrap();
+        history.save_post(&post("at://post/1")).unwrap();
+        history
+            .conn
+            .execute("UPDATE posts SET posted_at = ?1", [i64::MAX])
+            .unwrap();
+
+        assert!(history
+            .get_posts("spotbot.bsky.social", "10YFI-1--------U", post("").day)
+            .is_err());
+    }
+}
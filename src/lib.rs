@@ -0,0 +1,112 @@
+//! Day-ahead electricity spot prices from the ENTSO-E transparency platform.
+//!
+//! The library has the pieces of the bot for reuse in other services:
+//!
+//! - [`entsoe`] and [`parser`] fetch and parse the prices, or
+//!   [`fetch_day_prices`] does both for a delivery day
+//! - [`Price`] is a slot's start time and price, series are slices of them in
+//!   chronological order
+//! - [`convert_prices`] converts €/MWh to the local currency per kWh with
+//!   [`vat`], as set up by [`get_localization`]
+//! - [`statistics`] calculates the [`Aggregates`] and cheapest times
+//! - [`plotter`] renders the chart, with the `plot` feature
+//! - [`publisher`] posts a [`publisher::DailyReport`] to the configured
+//!   services, and [`pipeline::run`] does a whole run for one account
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let day = chrono::NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+//! let localization =
+//!     spotbot::get_localization(chrono::Locale::fi_FI, spotbot::currencies::get_currencies()?);
+//! let prices =
+//!     spotbot::fetch_day_prices("apikey", "10YFI-1--------U", day, &localization.timezone).await?;
+//! let vat = spotbot::vat::Vat::for_country("FI", spotbot::vat::NegativePricePolicy::Exempt)
+//!     .ok_or("unknown country")?;
+//! let converted = spotbot::convert_prices(&prices, &localization, &vat);
+//! let aggregates = spotbot::calculate_aggregates(
+//!     &converted,
+//!     &localization.timezone,
+//!     spotbot::HourRange { from: 8, to: 20 },
+//! );
+//! println!("average {:.2} c/kWh", aggregates.avg);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Cargo features, all enabled by default:
+//!
+//! - `plot` for charts, with the plotters and image dependencies
+//! - `bluesky` for the Bluesky publisher, reply bot and profile updates,
+//!   needs `plot`
+//! - `cli` for the spotbot binary, needs `plot`
+
+/// Bluesky posting, records and replies
+#[cfg(feature = "bluesky")]
+pub mod bluesky;
+/// Image compression to fit upload limits
+#[cfg(feature = "plot")]
+pub mod compressor;
+/// Account configuration from environment variables
+pub mod config;
+/// Exchange rates from EUR to the local currencies
+pub mod currencies;
+/// Long-running mode posting on each account's schedule
+#[cfg(feature = "plot")]
+pub mod daemon;
+/// ENTSO-E transparency platform API and bidding zones
+pub mod entsoe;
+/// Local SQLite history of prices, posts and replies
+pub mod history;
+/// Texts, number formats and currencies of the supported locales
+pub mod localization;
+/// Mastodon publisher
+pub mod mastodon;
+/// Matrix publisher
+pub mod matrix;
+/// MQTT publisher and Home Assistant discovery
+pub mod mqtt;
+/// ENTSO-E price document parser
+pub mod parser;
+/// A whole run for one account: fetch, analyze, plot and publish
+#[cfg(feature = "plot")]
+pub mod pipeline;
+/// Price charts
+#[cfg(feature = "plot")]
+pub mod plotter;
+/// Post texts and price tables
+pub mod poster;
+/// Price series, conversion and fetching
+pub mod prices;
+/// Bluesky profile updates
+#[cfg(feature = "bluesky")]
+pub mod profile;
+/// The publisher interface and the report it publishes
+pub mod publisher;
+/// Price questions in natural language
+pub mod queries;
+/// Answers to price questions in Bluesky mentions
+#[cfg(feature = "bluesky")]
+pub mod replies;
+/// Bluesky rich text with facets
+#[cfg(feature = "bluesky")]
+pub mod richtext;
+/// Bluesky sessions saved between runs
+#[cfg(feature = "bluesky")]
+pub mod session;
+/// Aggregates and cheapest times of price series
+pub mod statistics;
+/// Telegram publisher
+pub mod telegram;
+/// VAT rates by country and date
+pub mod vat;
+/// Webhook publisher
+pub mod webhook;
+
+pub use localization::{get_day_title, get_localization, Localization};
+pub use prices::{
+    convert_prices, fetch_day_prices, get_day_bounds, get_resolution, get_series_record, Price,
+};
+pub use statistics::{
+    analyze, calculate_aggregates, find_cheapest_slots, find_cheapest_window, Aggregates,
+    CheapestQuery, CheapestSlots, HourRange, Statistic,
+};
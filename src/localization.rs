@@ -0,0 +1,380 @@
+use chrono::NaiveDate;
+use chrono_tz::Europe::{Helsinki, Riga, Stockholm, Tallinn, Vilnius};
+use chrono_tz::{Tz, CET};
+
+use crate::currencies;
+
+/// timezone, formats, texts and currency of a locale
+#[derive(Clone, Debug)]
+pub struct Localization {
+    pub timezone: Tz,
+    pub chrono_locale: chrono::Locale,
+    pub day_name_locale: chrono::Locale,
+    pub num_locale: num_format::Locale,
+    pub plot_y_desc: &'static str,
+    pub plot_x_desc: &'static str,
+    pub plot_limit_multiplier: f32,
+    pub post_title: &'static str,
+    pub post_correction: &'static str,
+    pub post_at: &'static str,
+    pub post_avg: &'static str,
+    pub post_min: &'static str,
+    pub post_max: &'static str,
+    pub post_median: &'static str,
+    pub post_p10: &'static str,
+    pub post_p90: &'static str,
+    pub post_base: &'static str,
+    pub post_peak: &'static str,
+    pub post_weekdays: &'static str,
+    pub post_off_peak: &'static str,
+    pub post_spread: &'static str,
+    pub post_std_dev: &'static str,
+    pub post_vs_today: &'static str,
+    pub post_vs_last_week: &'static str,
+    pub post_vs_30d: &'static str,
+    pub post_cheapest_window: &'static str,
+    pub post_cheapest_slots: &'static str,
+    pub alt_chart: &'static str,
+    pub alt_cheapest: &'static str,
+    pub alt_most_expensive: &'static str,
+    /// night, morning, afternoon and evening
+    pub alt_parts_of_day: [&'static str; 4],
+    pub alt_hourly: &'static str,
+    /// keywords recognized in questions, in lowercase
+    pub query_cheapest: &'static [&'static str],
+    pub query_today: &'static [&'static str],
+    pub query_tomorrow: &'static [&'static str],
+    pub reply_help: &'static str,
+    pub reply_no_prices: &'static str,
+    pub post_vat: &'static str,
+    pub post_vat_negative: &'static str,
+    pub plot_vat: &'static str,
+    pub currency_name: &'static str,
+    pub currency_multiplier: f32,
+}
+
+/// localization of a supported locale, English texts in CET and euro cents for others
+pub fn get_localization(
+    locale: chrono::Locale,
+    currencies: currencies::Currencies,
+) -> Localization {
+    match locale {
+        chrono::Locale::fi_FI => Localization {
+            timezone: Helsinki,
+            chrono_locale: locale,
+            day_name_locale: locale,
+            num_locale: num_format::Locale::fi,
+            plot_y_desc: "hinta c/kWh",
+            plot_x_desc: "tunti",
+            plot_limit_multiplier: 1.0,
+            post_title: "Pörssisähkön spot-hinnat",
+            post_correction: "Korjatut spot-hinnat",
+            post_at: "klo",
+            post_avg: "Keskiarvo",
+            post_min: "Minimi",
+            post_max: "Maksimi",
+            post_median: "Mediaani",
+            post_p10: "10. persentiili",
+            post_p90: "90. persentiili",
+            post_base: "Perushinta",
+            post_peak: "Huippuhinta",
+            post_weekdays: "ma-pe",
+            post_off_peak: "Muut tunnit",
+            post_spread: "Vaihteluväli",
+            post_std_dev: "Keskihajonta",
+            post_vs_today: "Vs. tänään",
+            post_vs_last_week: "Vs. viikko sitten",
+            post_vs_30d: "Vs. 30 päivän keskiarvo",
+            post_cheapest_window: "Halvin jakso",
+            post_cheapest_slots: "Halvimmat",
+            alt_chart: "Pylväskaavio pörssisähkön tuntihinnoista",
+            alt_cheapest: "Halvinta",
+            alt_most_expensive: "kalleinta",
+            alt_parts_of_day: ["yöllä", "aamulla", "iltapäivällä", "illalla"],
+            alt_hourly: "Tuntihinnat",
+            query_cheapest: &["halvin", "halvimmat", "halvinta", "halvimmillaan"],
+            query_today: &["tänään"],
+            query_tomorrow: &["huomenna", "huomiselle", "huominen"],
+            reply_help: "Kysy esimerkiksi \"halvin 3 h huomenna\", \"hinta klo 18\" tai \"keskihinta 18-20\".",
+            reply_no_prices: "Päivän hintoja ei ole vielä julkaistu.",
+            post_vat: "Hinnat sisältävät alv.",
+            post_vat_negative: "Negatiivisiin hintoihin ei lisätä alv:tä.",
+            plot_vat: "sis. alv",
+            currency_name: "c",
+            currency_multiplier: currencies.eur_c,
+        },
+        chrono::Locale::et_EE => Localization {
+            timezone: Tallinn,
+            chrono_locale: chrono::Locale::et_EE,
+            day_name_locale: chrono::Locale::en_GB,
+            num_locale: num_format::Locale::et,
+            plot_y_desc: "price c/kWh",
+            plot_x_desc: "hour",
+            plot_limit_multiplier: 1.0,
+            post_title: "Electricity spot price",
+            post_correction: "Corrected spot prices",
+            post_at: "at",
+            post_avg: "Average",
+            post_min: "Minimum",
+            post_max: "Maximum",
+            post_median: "Median",
+            post_p10: "10th percentile",
+            post_p90: "90th percentile",
+            post_base: "Base",
+            post_peak: "Peak",
+            post_weekdays: "Mon-Fri",
+            post_off_peak: "Off-peak",
+            post_spread: "Spread",
+            post_std_dev: "Standard deviation",
+            post_vs_today: "Vs. today",
+            post_vs_last_week: "Vs. week ago",
+            post_vs_30d: "Vs. 30-day average",
+            post_cheapest_window: "Cheapest period",
+            post_cheapest_slots: "Cheapest",
+            alt_chart: "Bar chart of hourly electricity spot prices",
+            alt_cheapest: "Cheapest",
+            alt_most_expensive: "most expensive",
+            alt_parts_of_day: [
+                "at night",
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+            ],
+            alt_hourly: "Hourly prices",
+            query_cheapest: &["cheapest", "lowest"],
+            query_today: &["today"],
+            query_tomorrow: &["tomorrow"],
+            reply_help: "Ask for example \"cheapest 3 h tomorrow\", \"price at 18\" or \"average 18-20\".",
+            reply_no_prices: "The prices for the day have not been published yet.",
+            post_vat: "Prices include VAT",
+            post_vat_negative: "No VAT is added to negative prices.",
+            plot_vat: "incl. VAT",
+            currency_name: "c",
+            currency_multiplier: currencies.eur_c,
+        },
+        chrono::Locale::lt_LT => Localization {
+            timezone: Vilnius,
+            chrono_locale: locale,
+            day_name_locale: chrono::Locale::en_GB,
+            num_locale: num_format::Locale::lt,
+            plot_y_desc: "price c/kWh",
+            plot_x_desc: "hour",
+            plot_limit_multiplier: 1.0,
+            post_title: "Electricity spot price",
+            post_correction: "Corrected spot prices",
+            post_at: "at",
+            post_avg: "Average",
+            post_min: "Minimum",
+            post_max: "Maximum",
+            post_median: "Median",
+            post_p10: "10th percentile",
+            post_p90: "90th percentile",
+            post_base: "Base",
+            post_peak: "Peak",
+            post_weekdays: "Mon-Fri",
+            post_off_peak: "Off-peak",
+            post_spread: "Spread",
+            post_std_dev: "Standard deviation",
+            post_vs_today: "Vs. today",
+            post_vs_last_week: "Vs. week ago",
+            post_vs_30d: "Vs. 30-day average",
+            post_cheapest_window: "Cheapest period",
+            post_cheapest_slots: "Cheapest",
+            alt_chart: "Bar chart of hourly electricity spot prices",
+            alt_cheapest: "Cheapest",
+            alt_most_expensive: "most expensive",
+            alt_parts_of_day: [
+                "at night",
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+            ],
+            alt_hourly: "Hourly prices",
+            query_cheapest: &["cheapest", "lowest"],
+            query_today: &["today"],
+            query_tomorrow: &["tomorrow"],
+            reply_help: "Ask for example \"cheapest 3 h tomorrow\", \"price at 18\" or \"average 18-20\".",
+            reply_no_prices: "The prices for the day have not been published yet.",
+            post_vat: "Prices include VAT",
+            post_vat_negative: "No VAT is added to negative prices.",
+            plot_vat: "incl. VAT",
+            currency_name: "c",
+            currency_multiplier: currencies.eur_c,
+        },
+        chrono::Locale::lv_LV => Localization {
+            timezone: Riga,
+            chrono_locale: locale,
+            day_name_locale: chrono::Locale::en_GB,
+            num_locale: num_format::Locale::lv,
+            plot_y_desc: "price c/kWh",
+            plot_x_desc: "hour",
+            plot_limit_multiplier: 1.0,
+            post_title: "Electricity spot price",
+            post_correction: "Corrected spot prices",
+            post_at: "at",
+            post_avg: "Average",
+            post_min: "Minimum",
+            post_max: "Maximum",
+            post_median: "Median",
+            post_p10: "10th percentile",
+            post_p90: "90th percentile",
+            post_base: "Base",
+            post_peak: "Peak",
+            post_weekdays: "Mon-Fri",
+            post_off_peak: "Off-peak",
+            post_spread: "Spread",
+            post_std_dev: "Standard deviation",
+            post_vs_today: "Vs. today",
+            post_vs_last_week: "Vs. week ago",
+            post_vs_30d: "Vs. 30-day average",
+            post_cheapest_window: "Cheapest period",
+            post_cheapest_slots: "Cheapest",
+            alt_chart: "Bar chart of hourly electricity spot prices",
+            alt_cheapest: "Cheapest",
+            alt_most_expensive: "most expensive",
+            alt_parts_of_day: [
+                "at night",
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+            ],
+            alt_hourly: "Hourly prices",
+            query_cheapest: &["cheapest", "lowest"],
+            query_today: &["today"],
+            query_tomorrow: &["tomorrow"],
+            reply_help: "Ask for example \"cheapest 3 h tomorrow\", \"price at 18\" or \"average 18-20\".",
+            reply_no_prices: "The prices for the day have not been published yet.",
+            post_vat: "Prices include VAT",
+            post_vat_negative: "No VAT is added to negative prices.",
+            plot_vat: "incl. VAT",
+            currency_name: "c",
+            currency_multiplier: currencies.eur_c,
+        },
+        chrono::Locale::sv_SE => Localization {
+            timezone: Stockholm,
+            chrono_locale: locale,
+            day_name_locale: chrono::Locale::en_GB,
+            num_locale: num_format::Locale::sv,
+            plot_y_desc: "price öre/kWh",
+            plot_x_desc: "hour",
+            plot_limit_multiplier: 10.0,
+            post_title: "Electricity spot price",
+            post_correction: "Corrected spot prices",
+            post_at: "at",
+            post_avg: "Average",
+            post_min: "Minimum",
+            post_max: "Maximum",
+            post_median: "Median",
+            post_p10: "10th percentile",
+            post_p90: "90th percentile",
+            post_base: "Base",
+            post_peak: "Peak",
+            post_weekdays: "Mon-Fri",
+            post_off_peak: "Off-peak",
+            post_spread: "Spread",
+            post_std_dev: "Standard deviation",
+            post_vs_today: "Vs. today",
+            post_vs_last_week: "Vs. week ago",
+            post_vs_30d: "Vs. 30-day average",
+            post_cheapest_window: "Cheapest period",
+            post_cheapest_slots: "Cheapest",
+            alt_chart: "Bar chart of hourly electricity spot prices",
+            alt_cheapest: "Cheapest",
+            alt_most_expensive: "most expensive",
+            alt_parts_of_day: [
+                "at night",
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+            ],
+            alt_hourly: "Hourly prices",
+            query_cheapest: &["cheapest", "lowest"],
+            query_today: &["today"],
+            query_tomorrow: &["tomorrow"],
+            reply_help: "Ask for example \"cheapest 3 h tomorrow\", \"price at 18\" or \"average 18-20\".",
+            reply_no_prices: "The prices for the day have not been published yet.",
+            post_vat: "Prices include VAT",
+            post_vat_negative: "No VAT is added to negative prices.",
+            plot_vat: "incl. VAT",
+            currency_name: "öre",
+            currency_multiplier: currencies.sek_ore,
+        },
+        _ => Localization {
+            timezone: CET,
+            chrono_locale: locale,
+            day_name_locale: chrono::Locale::en_GB,
+            num_locale: num_format::Locale::en,
+            plot_y_desc: "price c/kWh",
+            plot_x_desc: "hour",
+            plot_limit_multiplier: 1.0,
+            post_title: "Electricity spot prices",
+            post_correction: "Corrected spot prices",
+            post_at: "at",
+            post_avg: "Average",
+            post_min: "Minimum",
+            post_max: "Maximum",
+            post_median: "Median",
+            post_p10: "10th percentile",
+            post_p90: "90th percentile",
+            post_base: "Base",
+            post_peak: "Peak",
+            post_weekdays: "Mon-Fri",
+            post_off_peak: "Off-peak",
+            post_spread: "Spread",
+            post_std_dev: "Standard deviation",
+            post_vs_today: "Vs. today",
+            post_vs_last_week: "Vs. week ago",
+            post_vs_30d: "Vs. 30-day average",
+            post_cheapest_window: "Cheapest period",
+            post_cheapest_slots: "Cheapest",
+            alt_chart: "Bar chart of hourly electricity spot prices",
+            alt_cheapest: "Cheapest",
+            alt_most_expensive: "most expensive",
+            alt_parts_of_day: [
+                "at night",
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+            ],
+            alt_hourly: "Hourly prices",
+            query_cheapest: &["cheapest", "lowest"],
+            query_today: &["today"],
+            query_tomorrow: &["tomorrow"],
+            reply_help: "Ask for example \"cheapest 3 h tomorrow\", \"price at 18\" or \"average 18-20\".",
+            reply_no_prices: "The prices for the day have not been published yet.",
+            post_vat: "Prices include VAT",
+            post_vat_negative: "No VAT is added to negative prices.",
+            plot_vat: "incl. VAT",
+            currency_name: "c",
+            currency_multiplier: currencies.eur_c,
+        },
+    }
+}
+
+/// weekday and date in the locale's format
+pub fn get_day_title(date: &NaiveDate, localization: &Localization) -> String {
+    date.format_localized("%A", localization.day_name_locale)
+        .to_string()
+        + " "
+        + &date
+            .format_localized("%x", localization.chrono_locale)
+            .to_string()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // fixed exchange rates, without the exchange rate file
+    pub fn localization(locale: chrono::Locale) -> Localization {
+        get_localization(
+            locale,
+            currencies::Currencies {
+                eur_c: 100.0,
+                dkk: 7.46,
+                nok: 11.7,
+                sek_ore: 1150.0,
+            },
+        )
+    }
+}
@@ -0,0 +1,492 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::publisher::{DailyReport, ExistingPost, Publisher};
+
+// defaults if the instance doesn't tell its limits
+static DEFAULT_MAX_CHARACTERS: usize = 500;
+static DEFAULT_DESCRIPTION_LIMIT: usize = 1500;
+// URLs count as this many characters regardless of their length
+static DEFAULT_CHARACTERS_PER_URL: usize = 23;
+
+// upper limit for paging the account's statuses when looking for an existing post
+static MAX_STATUS_PAGES: usize = 10;
+
+#[derive(Deserialize, Debug, Default)]
+struct StatusesConfiguration {
+    max_characters: Option<usize>,
+    characters_reserved_per_url: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct MediaConfiguration {
+    description_limit: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct InstanceConfiguration {
+    #[serde(default)]
+    statuses: StatusesConfiguration,
+    #[serde(default)]
+    media_attachments: MediaConfiguration,
+}
+
+#[derive(Deserialize, Debug)]
+struct Instance {
+    #[serde(default)]
+    configuration: InstanceConfiguration,
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaAttachment {
+    id: String,
+    url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Account {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Status {
+    #[serde(default)]
+    id: String,
+    uri: String,
+    url: Option<String>,
+    // HTML
+    #[serde(default)]
+    content: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+pub struct MastodonPublisher {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+    max_characters: usize,
+    characters_per_url: usize,
+    description_limit: usize,
+}
+
+impl MastodonPublisher {
+    pub async fn new(config: &Config) -> Result<MastodonPublisher, Box<dyn std::error::Error>> {
+        let base_url = config
+            .mastodon_url
+            .clone()
+            .ok_or("MASTODON_URL must be set for the mastodon publisher")?
+            .trim_end_matches('/')
+            .to_string();
+        let token = config
+            .mastodon_token
+            .clone()
+            .ok_or("MASTODON_TOKEN must be set for the mastodon publisher")?;
+        let client = reqwest::Client::new();
+
+        let instance: Instance = client
+            .get(format!("{}/api/v2/instance", base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let configuration = instance.configuration;
+
+        Ok(MastodonPublisher {
+            client,
+            base_url,
+            token,
+            max_characters: configuration
+                .statuses
+                .max_characters
+                .unwrap_or(DEFAULT_MAX_CHARACTERS),
+            characters_per_url: configuration
+                .statuses
+                .characters_reserved_per_url
+                .unwrap_or(DEFAULT_CHARACTERS_PER_URL),
+            description_limit: configuration
+                .media_attachments
+                .description_limit
+                .unwrap_or(DEFAULT_DESCRIPTION_LIMIT),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // report text within the instance's character limit, followed by the link and hashtags
+    fn compose(&self, report: &DailyReport) -> String {
+        let mut extras = String::new();
+        let mut extras_length = 0;
+        if let Some(link) = &report.link {
+            extras.push_str(&format!("\n\n{}", link));
+            extras_length += 2 + self.characters_per_url;
+        }
+        if !report.tags.is_empty() {
+            let tags = report
+                .tags
+                .iter()
+                .map(|tag| format!("#{}", tag))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let separator = if report.link.is_some() { "\n" } else { "\n\n" };
+            extras_length += separator.chars().count() + tags.chars().count();
+            extras.push_str(separator);
+            extras.push_str(&tags);
+        }
+
+        let text = report.fit_text(self.max_characters.saturating_sub(extras_length), |s| {
+            s.chars().count()
+        });
+        text + &extras
+    }
+
+    async fn upload_media(
+        &self,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let file = Part::bytes(report.image.clone())
+            .file_name(report.image_filename.clone())
+            .mime_str("image/png")?;
+        let form = Form::new()
+            .part("file", file)
+            .text("description", report.alt_text.fit(self.description_limit));
+
+        let mut media: MediaAttachment = self
+            .client
+            .post(self.url("/api/v2/media"))
+            .bearer_auth(&self.token)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // large media is processed asynchronously, url is set when it's ready
+        for _ in 0..10 {
+            if media.url.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            media = self
+                .client
+                .get(self.url(&format!("/api/v1/media/{}", media.id)))
+                .bearer_auth(&self.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+        }
+
+        Ok(media.id)
+    }
+
+    // the report with its chart, optionally as a reply to an earlier status
+    async fn post_status(
+        &self,
+        report: &DailyReport,
+        in_reply_to_id: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let media_id = self.upload_media(report).await?;
+        let key = report.post_key();
+
+        let status: Status = self
+            .client
+            .post(self.url("/api/v1/statuses"))
+            .bearer_auth(&self.token)
+            // retries of the same day's post are not duplicated
+            .header(
+                "Idempotency-Key",
+                match in_reply_to_id {
+                    Some(id) => format!("{}:{}", key, id),
+                    None => key,
+                },
+            )
+            .json(&json!({
+                "status": self.compose(report),
+                "media_ids": [media_id],
+                "in_reply_to_id": in_reply_to_id,
+                "visibility": "public",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(status.url.unwrap_or(status.uri))
+    }
+}
+
+// the id is the last part of both the status URL and URI
+fn status_id(uri: &str) -> Result<&str, String> {
+    uri.rsplit('/')
+        .next()
+        .filter(|id| !id.is_empty())
+        .ok_or(format!("Invalid status URL: {}", uri))
+}
+
+#[async_trait(?Send)]
+impl Publisher for MastodonPublisher {
+    fn name(&self) -> &'static str {
+        "mastodon"
+    }
+
+    // Statuses have no hidden fields for the post marker, so the account's
+    // own statuses are searched for the day title
+    async fn find_existing(
+        &self,
+        _area: &str,
+        day: NaiveDate,
+        day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        // posts are made the day before delivery, or later for backfills
+        let since = (day - Duration::days(2)).and_time(NaiveTime::MIN).and_utc();
+        let account: Account = self
+            .client
+            .get(self.url("/api/v1/accounts/verify_credentials"))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let mut max_id: Option<String> = None;
+        for _ in 0..MAX_STATUS_PAGES {
+            let mut request = self
+                .client
+                .get(self.url(&format!("/api/v1/accounts/{}/statuses", account.id)))
+                .bearer_auth(&self.token)
+                .query(&[("exclude_reblogs", "true"), ("limit", "40")]);
+            if let Some(max_id) = &max_id {
+                request = request.query(&[("max_id", max_id)]);
+            }
+            let statuses: Vec<Status> = request.send().await?.error_for_status()?.json().await?;
+
+            // newest first
+            for status in &statuses {
+                if status
+                    .created_at
+                    .is_some_and(|created_at| created_at < since)
+                {
+                    return Ok(None);
+                }
+                if status.content.contains(day_title) {
+                    return Ok(Some(ExistingPost {
+                        uri: status.url.clone().unwrap_or(status.uri.clone()),
+                        matched_by: "title",
+                    }));
+                }
+            }
+            match statuses.last() {
+                Some(last) => max_id = Some(last.id.clone()),
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(format!(
+            "{}\n\nAlt text:\n{}",
+            self.compose(report),
+            report.alt_text.fit(self.description_limit)
+        ))
+    }
+
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        self.post_status(report, None).await
+    }
+
+    async fn reply(
+        &self,
+        uri: &str,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.post_status(report, Some(status_id(uri)?)).await
+    }
+
+    async fn delete(&self, uri: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.client
+            .delete(self.url(&format!("/api/v1/statuses/{}", status_id(uri)?)))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::publisher::tests::sample_report;
+    use mockito::Matcher;
+
+    async fn publisher(server: &mut mockito::Server) -> MastodonPublisher {
+        server
+            .mock("GET", "/api/v2/instance")
+            .with_body(r#"{"configuration": {"statuses": {"max_characters": 500}}}"#)
+            .create_async()
+            .await;
+        MastodonPublisher::new(&config(&[
+            ("MASTODON_URL", &server.url()),
+            ("MASTODON_TOKEN", "token"),
+        ]))
+        .await
+        .unwrap()
+    }
+
+    fn media_mock(server: &mut mockito::Server) -> mockito::Mock {
+        server
+            .mock("POST", "/api/v2/media")
+            .match_header("authorization", "Bearer token")
+            .match_header(
+                "content-type",
+                Matcher::Regex("^multipart/form-data".to_string()),
+            )
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#"name="file"; filename="chart.png""#.to_string()),
+                Matcher::Regex(r#"name="description""#.to_string()),
+            ]))
+            .with_body(r#"{"id": "5", "url": "https://example.social/media/5.png"}"#)
+    }
+
+    fn status(id: u32, content: &str, created_at: &str) -> serde_json::Value {
+        json!({
+            "id": id.to_string(),
+            "uri": format!("https://example.social/users/spotbot/statuses/{}", id),
+            "url": format!("https://example.social/@spotbot/{}", id),
+            "content": content,
+            "created_at": created_at,
+        })
+    }
+
+    #[tokio::test]
+    async fn finds_posts_by_day_title() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = publisher(&mut server).await;
+        let report = sample_report();
+        server
+            .mock("GET", "/api/v1/accounts/verify_credentials")
+            .match_header("authorization", "Bearer token")
+            .with_body(r#"{"id": "7"}"#)
+            .create_async()
+            .await;
+        let first = server
+            .mock("GET", "/api/v1/accounts/7/statuses")
+            .match_query(Matcher::Regex(
+                "^exclude_reblogs=true&limit=40$".to_string(),
+            ))
+            .with_body(
+                json!([
+                    status(21, "<p>Hello</p>", "2025-01-01T14:00:00Z"),
+                    status(20, "<p>Other prices</p>", "2025-01-01T13:00:00Z"),
+                ])
+                .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let second = server
+            .mock("GET", "/api/v1/accounts/7/statuses")
+            .match_query(Matcher::UrlEncoded("max_id".to_string(), "20".to_string()))
+            .with_body(
+                json!([
+                    status(
+                        19,
+                        &format!("<p>{}</p>", report.blocks[0]),
+                        "2025-01-01T12:00:00Z"
+                    ),
+                    status(18, "<p>Old</p>", "2024-12-20T12:00:00Z"),
+                ])
+                .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let existing = publisher
+            .find_existing(&report.area, report.day, &report.day_title)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(existing.uri, "https://example.social/@spotbot/19");
+        assert_eq!(existing.matched_by, "title");
+
+        // statuses older than the day before are not searched further
+        let next_day = report.day + Duration::days(1);
+        let day_title = crate::get_day_title(
+            &next_day,
+            &crate::localization::tests::localization(chrono::Locale::fi_FI),
+        );
+        assert!(publisher
+            .find_existing(&report.area, next_day, &day_title)
+            .await
+            .unwrap()
+            .is_none());
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn publishes_with_media_and_idempotency_key() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = publisher(&mut server).await;
+        let media = media_mock(&mut server).create_async().await;
+        let status = server
+            .mock("POST", "/api/v1/statuses")
+            .match_header("idempotency-key", "spotbot:10YFI-1--------U:2025-01-02")
+            .match_body(Matcher::PartialJson(json!({
+                "media_ids": ["5"],
+                "in_reply_to_id": null,
+                "visibility": "public",
+            })))
+            .with_body(r#"{"uri": "https://example.social/users/spotbot/statuses/9", "url": "https://example.social/@spotbot/9"}"#)
+            .create_async()
+            .await;
+
+        let uri = publisher.publish(&sample_report()).await.unwrap();
+        assert_eq!(uri, "https://example.social/@spotbot/9");
+        media.assert_async().await;
+        status.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn corrections_get_their_own_idempotency_keys() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = publisher(&mut server).await;
+        media_mock(&mut server).expect(2).create_async().await;
+        let reply = server
+            .mock("POST", "/api/v1/statuses")
+            .match_header("idempotency-key", "spotbot:10YFI-1--------U:2025-01-02:9")
+            .match_body(Matcher::PartialJson(json!({ "in_reply_to_id": "9" })))
+            .with_body(r#"{"uri": "https://example.social/users/spotbot/statuses/10"}"#)
+            .create_async()
+            .await;
+        let report = sample_report().revised();
+        let repost = server
+            .mock("POST", "/api/v1/statuses")
+            .match_header("idempotency-key", report.post_key().as_str())
+            .with_body(r#"{"uri": "https://example.social/users/spotbot/statuses/11"}"#)
+            .create_async()
+            .await;
+
+        publisher
+            .reply("https://example.social/@spotbot/9", &sample_report())
+            .await
+            .unwrap();
+        publisher.publish(&report).await.unwrap();
+        reply.assert_async().await;
+        repost.assert_async().await;
+    }
+}
@@ -0,0 +1,434 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::publisher::{post_marker, DailyReport, ExistingPost, Publisher};
+
+// custom event content field carrying the post marker
+static MARKER_FIELD: &str = "app.spotbot.marker";
+
+// upper limit for paging room history when looking for an existing post
+static MAX_MESSAGE_PAGES: usize = 10;
+
+#[derive(Deserialize, Debug)]
+struct Upload {
+    content_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SentEvent {
+    event_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RoomEvent {
+    event_id: String,
+    #[serde(default)]
+    content: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct Messages {
+    chunk: Vec<RoomEvent>,
+    end: Option<String>,
+}
+
+pub struct MatrixPublisher {
+    client: reqwest::Client,
+    homeserver: Url,
+    token: String,
+    rooms: Vec<String>,
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl MatrixPublisher {
+    pub fn new(config: &Config) -> Result<MatrixPublisher, Box<dyn std::error::Error>> {
+        let homeserver = config
+            .matrix_url
+            .as_deref()
+            .ok_or("MATRIX_URL must be set for the matrix publisher")?;
+        // e.g. "localhost:8008" parses as a URL with the scheme "localhost"
+        let homeserver = Url::parse(homeserver)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base() && url.has_host())
+            .ok_or(format!(
+                "MATRIX_URL must be a URL like https://matrix.example.org: {}",
+                homeserver
+            ))?;
+        let rooms = config.matrix_rooms.clone();
+        if rooms.is_empty() {
+            return Err("MATRIX_ROOMS must be set for the matrix publisher".into());
+        }
+
+        Ok(MatrixPublisher {
+            client: reqwest::Client::new(),
+            homeserver,
+            token: config
+                .matrix_token
+                .clone()
+                .ok_or("MATRIX_TOKEN must be set for the matrix publisher")?,
+            rooms,
+        })
+    }
+
+    // homeserver URL with percent-encoded path segments
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("MATRIX_URL is checked to be a base URL")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn compose_text(&self, report: &DailyReport) -> String {
+        let mut text = report.blocks.join("\n\n");
+        if let Some(link) = &report.link {
+            text.push_str(&format!("\n\n{}", link));
+        }
+        if !report.tags.is_empty() {
+            text.push_str(if report.link.is_some() { "\n" } else { "\n\n" });
+            text.push_str(&Self::format_tags(report));
+        }
+        text
+    }
+
+    fn compose_html(&self, report: &DailyReport) -> String {
+        let mut blocks = report
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let lines = block
+                    .lines()
+                    .map(escape_html)
+                    .collect::<Vec<_>>()
+                    .join("<br>");
+                // header in bold
+                if i == 0 {
+                    format!("<p><strong>{}</strong></p>", lines)
+                } else {
+                    format!("<p>{}</p>", lines)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut extras = Vec::new();
+        if let Some(link) = &report.link {
+            let link = escape_html(link);
+            extras.push(format!("<a href=\"{}\">{}</a>", link, link));
+        }
+        if !report.tags.is_empty() {
+            extras.push(escape_html(&Self::format_tags(report)));
+        }
+        if !extras.is_empty() {
+            blocks.push(format!("<p>{}</p>", extras.join("<br>")));
+        }
+        blocks.join("")
+    }
+
+    fn format_tags(report: &DailyReport) -> String {
+        report
+            .tags
+            .iter()
+            .map(|tag| format!("#{}", tag))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    async fn upload(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let mut url = self.url(&["_matrix", "media", "v3", "upload"]);
+        url.query_pairs_mut()
+            .append_pair("filename", &report.image_filename);
+
+        let upload: Upload = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .header("Content-Type", "image/png")
+            .body(report.image.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(upload.content_uri)
+    }
+
+    // transaction ids derived from the post marker make the homeserver
+    // ignore retries of an already sent event
+    async fn send(
+        &self,
+        room: &str,
+        txn_id: &str,
+        content: &Value,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let event: SentEvent = self
+            .client
+            .put(self.url(&[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room,
+                "send",
+                "m.room.message",
+                txn_id,
+            ]))
+            .bearer_auth(&self.token)
+            .json(content)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(event.event_id)
+    }
+
+    async fn find_in_room(
+        &self,
+        room: &str,
+        marker: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let mut from: Option<String> = None;
+        for _ in 0..MAX_MESSAGE_PAGES {
+            let mut url = self.url(&["_matrix", "client", "v3", "rooms", room, "messages"]);
+            url.query_pairs_mut()
+                .append_pair("dir", "b")
+                .append_pair("limit", "50");
+            if let Some(from) = &from {
+                url.query_pairs_mut().append_pair("from", from);
+            }
+
+            let messages: Messages = self
+                .client
+                .get(url)
+                .bearer_auth(&self.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            if let Some(event) = messages
+                .chunk
+                .iter()
+                .find(|event| event.content[MARKER_FIELD].as_str() == Some(marker))
+            {
+                return Ok(Some(event.event_id.clone()));
+            }
+
+            match messages.end {
+                Some(end) if !messages.chunk.is_empty() => from = Some(end),
+                _ => break,
+            }
+        }
+        Ok(None)
+    }
+}
+
+// width and height from the header of a PNG image
+fn png_dimensions(png: &[u8]) -> Option<(u32, u32)> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+    let header = png.get(16..24)?;
+    let width = u32::from_be_bytes(header[..4].try_into().ok()?);
+    let height = u32::from_be_bytes(header[4..].try_into().ok()?);
+    Some((width, height))
+}
+
+#[async_trait(?Send)]
+impl Publisher for MatrixPublisher {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    // the day counts as posted only when every room has the post
+    async fn find_existing(
+        &self,
+        area: &str,
+        day: NaiveDate,
+        _day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        let marker = post_marker(area, day);
+        let mut events = Vec::new();
+        for room in &self.rooms {
+            match self.find_in_room(room, &marker).await? {
+                Some(event_id) => events.push(format!("{}/{}", room, event_id)),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(ExistingPost {
+            uri: events.join(" "),
+            matched_by: "marker",
+        }))
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(format!(
+            "{}\n\nHTML:\n{}\n\nAlt text:\n{}",
+            self.compose_text(report),
+            self.compose_html(report),
+            report.alt_text.fit(usize::MAX)
+        ))
+    }
+
+    // returns "<room id>/<event id>" of the text messages separated by spaces
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let (width, height) = png_dimensions(&report.image).ok_or("The chart is not a PNG")?;
+        let content_uri = self.upload(report).await?;
+        let marker = post_marker(&report.area, report.day);
+        // re-posts get new transaction ids, the marker stays the same
+        let key = report.post_key();
+
+        let image = json!({
+            "msgtype": "m.image",
+            "body": report.alt_text.fit(usize::MAX),
+            "filename": report.image_filename,
+            "url": content_uri,
+            "info": {
+                "mimetype": "image/png",
+                "size": report.image.len(),
+                "w": width,
+                "h": height,
+            },
+        });
+        let text = json!({
+            "msgtype": "m.text",
+            "body": self.compose_text(report),
+            "format": "org.matrix.custom.html",
+            "formatted_body": self.compose_html(report),
+            MARKER_FIELD: marker,
+        });
+
+        let mut events = Vec::new();
+        for room in &self.rooms {
+            self.send(room, &format!("{}:image", key), &image).await?;
+            let event_id = self.send(room, &format!("{}:text", key), &text).await?;
+            events.push(format!("{}/{}", room, event_id));
+        }
+        Ok(events.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::publisher::tests::sample_report;
+    use mockito::Matcher;
+
+    #[test]
+    fn homeserver_must_be_a_base_url() {
+        for url in [
+            "localhost:8008",
+            "matrix.example.org",
+            "mailto:admin@example.org",
+        ] {
+            let result = MatrixPublisher::new(&config(&[
+                ("MATRIX_URL", url),
+                ("MATRIX_TOKEN", "token"),
+                ("MATRIX_ROOMS", "!room:example.org"),
+            ]));
+            assert!(result.err().unwrap().to_string().starts_with("MATRIX_URL"));
+        }
+    }
+
+    static SEND_PATH: &str = "/_matrix/client/v3/rooms/!room:example.org/send/m.room.message";
+
+    #[tokio::test]
+    async fn retries_reuse_transaction_ids() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = MatrixPublisher::new(&config(&[
+            ("MATRIX_URL", &server.url()),
+            ("MATRIX_TOKEN", "token"),
+            ("MATRIX_ROOMS", "!room:example.org"),
+        ]))
+        .unwrap();
+
+        let upload = server
+            .mock("POST", "/_matrix/media/v3/upload")
+            .match_query(Matcher::UrlEncoded(
+                "filename".to_string(),
+                "chart.png".to_string(),
+            ))
+            .match_header("content-type", "image/png")
+            .with_body(r#"{"content_uri": "mxc://example.org/chart"}"#)
+            .expect(3)
+            .create_async()
+            .await;
+        let image = server
+            .mock(
+                "PUT",
+                format!("{}/spotbot:10YFI-1--------U:2025-01-02:image", SEND_PATH).as_str(),
+            )
+            .match_body(Matcher::PartialJson(json!({
+                "msgtype": "m.image",
+                "url": "mxc://example.org/chart",
+                "info": { "w": 2, "h": 1 },
+            })))
+            .with_body(r#"{"event_id": "$image"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let text = server
+            .mock(
+                "PUT",
+                format!("{}/spotbot:10YFI-1--------U:2025-01-02:text", SEND_PATH).as_str(),
+            )
+            .match_header("authorization", "Bearer token")
+            .match_body(Matcher::PartialJson(json!({
+                "msgtype": "m.text",
+                "app.spotbot.marker": "spotbot:10YFI-1--------U:2025-01-02",
+            })))
+            .with_body(r#"{"event_id": "$text"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        // a re-post of corrected prices is a new transaction
+        let revised = sample_report().revised();
+        let revised_text = server
+            .mock(
+                "PUT",
+                format!("{}/{}:text", SEND_PATH, revised.post_key()).as_str(),
+            )
+            .with_body(r#"{"event_id": "$revised"}"#)
+            .create_async()
+            .await;
+        server
+            .mock(
+                "PUT",
+                format!("{}/{}:image", SEND_PATH, revised.post_key()).as_str(),
+            )
+            .with_body(r#"{"event_id": "$revised_image"}"#)
+            .create_async()
+            .await;
+
+        let report = sample_report();
+        assert_eq!(
+            publisher.publish(&report).await.unwrap(),
+            "!room:example.org/$text"
+        );
+        publisher.publish(&report).await.unwrap();
+        assert_eq!(
+            publisher.publish(&revised).await.unwrap(),
+            "!room:example.org/$revised"
+        );
+
+        upload.assert_async().await;
+        image.assert_async().await;
+        text.assert_async().await;
+        revised_text.assert_async().await;
+    }
+}
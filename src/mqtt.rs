@@ -0,0 +1,428 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use reqwest::Url;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::publisher::{self, DailyReport, ExistingPost, Publisher};
+use crate::{entsoe, get_resolution, Aggregates};
+
+static DEFAULT_PORT: u16 = 1883;
+static TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// topic, payload and whether the broker retains it
+type Message = (String, String, bool);
+
+pub struct MqttPublisher {
+    options: MqttOptions,
+    topic_prefix: String,
+    discovery_prefix: String,
+    area: String,
+}
+
+// area code like "FI" for known areas, the EIC code otherwise
+fn topic_area(eic: &str) -> String {
+    entsoe::get_area(eic)
+        .map(|(area, _)| area.to_string())
+        .unwrap_or(eic.to_string())
+}
+
+// The client id must be unique per connection, the broker drops the older
+// connection of the same id.
+fn get_options(
+    config: &Config,
+    client_id: String,
+) -> Result<MqttOptions, Box<dyn std::error::Error>> {
+    let url = Url::parse(
+        config
+            .mqtt_url
+            .as_deref()
+            .ok_or("MQTT_URL must be set for the mqtt publisher")?,
+    )?;
+    // TLS isn't supported, don't fall back to plain TCP for mqtts://
+    if !["mqtt", "tcp"].contains(&url.scheme()) {
+        return Err(format!("MQTT_URL must be mqtt://, not {}://", url.scheme()).into());
+    }
+    let mut options = MqttOptions::new(
+        client_id,
+        url.host_str().ok_or("MQTT_URL must have a host")?,
+        url.port().unwrap_or(DEFAULT_PORT),
+    );
+    if !url.username().is_empty() {
+        options.set_credentials(url.username(), url.password().unwrap_or_default());
+    }
+    Ok(options)
+}
+
+// publishes the messages and waits until the broker has acknowledged them
+async fn send(
+    options: MqttOptions,
+    messages: Vec<Message>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let count = messages.len();
+    let (client, mut eventloop) = AsyncClient::new(options, count + 1);
+    for (topic, payload, retain) in messages {
+        client.try_publish(topic, QoS::AtLeastOnce, retain, payload)?;
+    }
+
+    let mut acknowledged = 0;
+    tokio::time::timeout(TIMEOUT, async {
+        while acknowledged < count {
+            if let Event::Incoming(Packet::PubAck(_)) = eventloop.poll().await? {
+                acknowledged += 1;
+            }
+        }
+        Ok::<(), rumqttc::ConnectionError>(())
+    })
+    .await
+    .map_err(|_| "Timed out publishing to MQTT")??;
+
+    client.disconnect().await?;
+    Ok(())
+}
+
+impl MqttPublisher {
+    pub fn new(config: &Config) -> Result<MqttPublisher, Box<dyn std::error::Error>> {
+        Self::with_client_id(config, "")
+    }
+
+    // the client id is "spotbot-<area>" followed by the suffix
+    fn with_client_id(
+        config: &Config,
+        suffix: &str,
+    ) -> Result<MqttPublisher, Box<dyn std::error::Error>> {
+        let area = topic_area(&config.entsoe_eic);
+        Ok(MqttPublisher {
+            options: get_options(config, format!("spotbot-{}{}", area, suffix))?,
+            topic_prefix: config.mqtt_topic_prefix.clone(),
+            discovery_prefix: config.mqtt_discovery_prefix.clone(),
+            area,
+        })
+    }
+
+    fn topic(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.topic_prefix, self.area, path)
+    }
+
+    // Home Assistant discovery configs for the sensors updated by publish_state
+    fn discovery(&self, unit: &str) -> Vec<Message> {
+        let device = json!({
+            "identifiers": [format!("spotbot_{}", self.area)],
+            "name": format!("Spot price {}", self.area),
+            "manufacturer": "spotbot",
+        });
+        let sensor = |component: &str, object_id: &str, name: &str, mut config: Value| {
+            config["name"] = json!(name);
+            config["unique_id"] = json!(format!("spotbot_{}_{}", self.area, object_id));
+            config["state_topic"] = json!(self.topic(object_id));
+            config["device"] = device.clone();
+            (
+                format!(
+                    "{}/{}/spotbot_{}/{}/config",
+                    self.discovery_prefix, component, self.area, object_id
+                ),
+                config.to_string(),
+                true,
+            )
+        };
+
+        vec![
+            sensor(
+                "sensor",
+                "current_price",
+                "Current price",
+                json!({ "unit_of_measurement": unit, "state_class": "measurement" }),
+            ),
+            sensor(
+                "sensor",
+                "next_hour_price",
+                "Next hour price",
+                // empty until the next day's prices are known
+                json!({
+                    "unit_of_measurement": unit,
+                    "state_class": "measurement",
+                    "value_template": "{{ value if value else None }}",
+                }),
+            ),
+            sensor(
+                "binary_sensor",
+                "cheap_now",
+                "Cheap now",
+                json!({ "payload_on": "ON", "payload_off": "OFF" }),
+            ),
+        ]
+    }
+
+    fn compose(&self, report: &DailyReport) -> Vec<Message> {
+        let day = report.day.format("%Y-%m-%d").to_string();
+        let aggregates = &report.aggregates;
+
+        let mut messages = vec![
+            (
+                self.topic(&format!("{}/prices", day)),
+                json!(report
+                    .prices
+                    .iter()
+                    .map(publisher::price_json)
+                    .collect::<Vec<_>>())
+                .to_string(),
+                true,
+            ),
+            (
+                self.topic(&format!("{}/aggregates", day)),
+                json!({
+                    "unit": report.unit,
+                    "min": publisher::price_json(&aggregates.min),
+                    "max": publisher::price_json(&aggregates.max),
+                    "avg": aggregates.avg,
+                    "cheapest_window": publisher::cheapest_json(&aggregates.cheapest_window),
+                    "cheapest_slots": publisher::cheapest_json(&aggregates.cheapest_slots),
+                })
+                .to_string(),
+                true,
+            ),
+        ];
+        // one topic per slot, numbered from the start of the day
+        messages.extend(report.prices.iter().enumerate().map(|(i, point)| {
+            (
+                self.topic(&format!("{}/slots/{:02}", day, i)),
+                publisher::price_json(point).to_string(),
+                true,
+            )
+        }));
+        messages.extend(self.discovery(&report.unit));
+        messages
+    }
+}
+
+#[async_trait(?Send)]
+impl Publisher for MqttPublisher {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    // retained topics are simply overwritten, so only the local post log is
+    // checked for existing posts
+    async fn find_existing(
+        &self,
+        _area: &str,
+        _day: NaiveDate,
+        _day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self
+            .compose(report)
+            .iter()
+            .map(|(topic, payload, _)| format!("{} {}", topic, payload))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        send(self.options.clone(), self.compose(report)).await?;
+        Ok(self.topic(&report.day.format("%Y-%m-%d").to_string()))
+    }
+
+    // retained topics are replaced with the corrected prices
+    fn supports_update(&self) -> bool {
+        true
+    }
+
+    async fn update(
+        &self,
+        _uri: &str,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.publish(report).await
+    }
+}
+
+// Updates the current price, next hour price and cheap now states at the given
+// time. The slot is cheap if it's in the day's cheapest window or slots, or
+// below the day's average if neither is configured.
+pub async fn publish_state(
+    config: &Config,
+    prices: &[(DateTime<Utc>, f32)],
+    aggregates: &Aggregates,
+    now: DateTime<Utc>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let resolution = get_resolution(prices);
+    let price_at = |ts: DateTime<Utc>| {
+        prices
+            .iter()
+            .find(|(start, _)| *start <= ts && ts < *start + resolution)
+    };
+    let current = price_at(now).ok_or("No stored price for the current time")?;
+
+    let cheapest = [&aggregates.cheapest_window, &aggregates.cheapest_slots]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let cheap_now = if cheapest.is_empty() {
+        current.1 < aggregates.avg
+    } else {
+        cheapest
+            .iter()
+            .any(|cheapest| cheapest.slots.iter().any(|(ts, _)| *ts == current.0))
+    };
+
+    // a connection of its own, it may run while the daily post is published
+    let publisher = MqttPublisher::with_client_id(config, "-state")?;
+    let messages = vec![
+        (
+            publisher.topic("current_price"),
+            format!("{:.2}", current.1),
+            true,
+        ),
+        (
+            publisher.topic("cheap_now"),
+            if cheap_now { "ON" } else { "OFF" }.to_string(),
+            true,
+        ),
+        // empty if not known, the retained price of an earlier hour would be stale
+        (
+            publisher.topic("next_hour_price"),
+            price_at(now + Duration::hours(1))
+                .map(|(_, price)| format!("{:.2}", price))
+                .unwrap_or_default(),
+            true,
+        ),
+    ];
+    send(publisher.options, messages).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::publisher::tests::sample_report;
+    use bytes::BytesMut;
+    use rumqttc::mqttbytes::v4::{self, ConnAck, ConnectReturnCode, PubAck};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // client id and the messages published in a connection
+    type Connection = (String, Vec<Message>);
+
+    // Accepts one connection and acknowledges everything published in it
+    async fn broker() -> (u16, tokio::task::JoinHandle<Connection>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = BytesMut::new();
+            let mut client_id = String::new();
+            let mut messages = Vec::new();
+            loop {
+                let packet = match v4::read(&mut buffer, 1 << 20) {
+                    Ok(packet) => packet,
+                    Err(_) => {
+                        if stream.read_buf(&mut buffer).await.unwrap() == 0 {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let mut reply = BytesMut::new();
+                match packet {
+                    Packet::Connect(connect) => {
+                        client_id = connect.client_id;
+                        ConnAck::new(ConnectReturnCode::Success, false)
+                            .write(&mut reply)
+                            .unwrap();
+                    }
+                    Packet::Publish(publish) => {
+                        messages.push((
+                            publish.topic,
+                            String::from_utf8(publish.payload.to_vec()).unwrap(),
+                            publish.retain,
+                        ));
+                        PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                    }
+                    Packet::Disconnect => break,
+                    _ => {}
+                }
+                stream.write_all(&reply).await.unwrap();
+            }
+            (client_id, messages)
+        });
+        (port, handle)
+    }
+
+    fn payload<'a>(messages: &'a [Message], topic: &str) -> &'a Message {
+        messages
+            .iter()
+            .find(|(t, _, _)| t == topic)
+            .unwrap_or_else(|| panic!("{} not published", topic))
+    }
+
+    #[tokio::test]
+    async fn publishes_retained_prices_and_discovery() {
+        let (port, broker) = broker().await;
+        let url = format!("mqtt://127.0.0.1:{}", port);
+        let publisher = MqttPublisher::new(&config(&[("MQTT_URL", &url)])).unwrap();
+
+        let uri = publisher.publish(&sample_report()).await.unwrap();
+        assert_eq!(uri, "spotbot/FI/2025-01-02");
+
+        let (client_id, messages) = broker.await.unwrap();
+        assert_eq!(client_id, "spotbot-FI");
+        assert!(messages.iter().all(|(_, _, retain)| *retain));
+
+        let (_, prices, _) = payload(&messages, "spotbot/FI/2025-01-02/prices");
+        let prices: Vec<Value> = serde_json::from_str(prices).unwrap();
+        assert_eq!(prices.len(), 24);
+        payload(&messages, "spotbot/FI/2025-01-02/slots/23");
+
+        let (_, discovery, _) = payload(
+            &messages,
+            "homeassistant/sensor/spotbot_FI/current_price/config",
+        );
+        let discovery: Value = serde_json::from_str(discovery).unwrap();
+        assert_eq!(discovery["state_topic"], "spotbot/FI/current_price");
+        assert_eq!(discovery["unique_id"], "spotbot_FI_current_price");
+        payload(
+            &messages,
+            "homeassistant/binary_sensor/spotbot_FI/cheap_now/config",
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_retained_states() {
+        let (port, broker) = broker().await;
+        let url = format!("mqtt://127.0.0.1:{}", port);
+        let report = sample_report();
+        let (last, price) = *report.prices.last().unwrap();
+
+        publish_state(
+            &config(&[("MQTT_URL", &url)]),
+            &report.prices,
+            &report.aggregates,
+            last + Duration::minutes(30),
+        )
+        .await
+        .unwrap();
+
+        let (client_id, messages) = broker.await.unwrap();
+        assert_eq!(client_id, "spotbot-FI-state");
+        assert!(messages.iter().all(|(_, _, retain)| *retain));
+        assert_eq!(
+            payload(&messages, "spotbot/FI/current_price").1,
+            format!("{:.2}", price)
+        );
+        // the most expensive hour isn't cheap
+        assert_eq!(payload(&messages, "spotbot/FI/cheap_now").1, "OFF");
+        // no prices for the next hour yet
+        assert_eq!(payload(&messages, "spotbot/FI/next_hour_price").1, "");
+    }
+
+    #[test]
+    fn rejects_tls() {
+        let result = MqttPublisher::new(&config(&[("MQTT_URL", "mqtts://broker:8883")]));
+        assert!(result.is_err());
+    }
+}
@@ -44,7 +44,7 @@ fn on_characters(
                 *current_timestamp = Some(Utc.from_utc_datetime(&naive_time));
             }
             Err(e) => {
-                println!("Error parsing timestamp: {}", e);
+                log::warn!("Error parsing timestamp: {}", e);
             }
         }
     } else if current_element == "resolution" {
@@ -103,7 +103,7 @@ pub fn parse_xml(prices_xml: String) -> Vec<(DateTime<Utc>, f32)> {
                 );
             }
             Err(e) => {
-                println!("Error: {}", e);
+                log::warn!("Error parsing prices: {}", e);
                 break;
             }
             _ => {}
@@ -0,0 +1,630 @@
+use chrono::{Duration, NaiveDate, Utc};
+
+use crate::config::{self, Config, Mode};
+use crate::localization::{get_day_title, get_localization, Localization};
+use crate::prices::{
+    convert_prices, fetch_day_prices, get_day_bounds, get_history_average, get_max_difference,
+    get_resolution, get_series_record, get_stored_prices, Price,
+};
+use crate::publisher::Publisher;
+use crate::statistics::analyze;
+use crate::{currencies, history, mqtt, plotter, poster, publisher, vat};
+
+/// plots the chart of prices in €/MWh without posting it
+pub fn render_chart(
+    config: &Config,
+    localization: &Localization,
+    day: NaiveDate,
+    raw_prices: &[Price],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if raw_prices.is_empty() {
+        return Err(format!("No prices for {}", day).into());
+    }
+    let prices = convert_prices(raw_prices, localization, &config.vat);
+    let aggregates = analyze(&prices, config, localization);
+    let plot_filename = get_plot_filename(config, day);
+
+    log::info!("Plotting graph");
+    plotter::plot(
+        &plot_filename,
+        &prices,
+        &aggregates,
+        &get_day_title(&day, localization),
+        localization,
+        config.vat.rate(day),
+    )?;
+    log::info!("Image: {}", plot_filename);
+    Ok(())
+}
+
+/// delivery day of the run, tomorrow by default
+pub fn get_day(config: &Config, localization: &Localization) -> NaiveDate {
+    config.date.unwrap_or_else(|| {
+        (Utc::now() + Duration::days(1))
+            .with_timezone(&localization.timezone)
+            .date_naive()
+    })
+}
+
+/// chart file of the run, `<account>-<date>.png` by default
+pub fn get_plot_filename(config: &Config, day: NaiveDate) -> String {
+    config
+        .output
+        .clone()
+        .unwrap_or(format!("{}-{}.png", config.account, day.format("%Y-%m-%d")))
+}
+
+#[cfg(feature = "bluesky")]
+async fn answer_mentions(
+    config: &Config,
+    localization: &Localization,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::{replies, session};
+
+    let history = history::History::open(&config.history_db)?;
+    let (username, password) = config.bluesky_credentials()?;
+    let (agent, session) = session::get_agent(username, password, &config.session_file).await?;
+
+    log::info!("Answering mentions");
+    replies::answer_mentions(&agent, &session, &history, config, localization).await?;
+    Ok(())
+}
+
+#[cfg(not(feature = "bluesky"))]
+async fn answer_mentions(
+    _config: &Config,
+    _localization: &Localization,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("Answering mentions needs the bluesky feature".into())
+}
+
+/// a single run for one account and delivery day
+pub async fn run(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let currencies = currencies::get_currencies()?;
+
+    let localization = get_localization(config.locale, currencies);
+    let day = get_day(config, &localization);
+    let day_title = get_day_title(&day, &localization);
+    let vat_rate = config.vat.rate(day);
+    let area = &config.entsoe_eic;
+    let plot_filename = get_plot_filename(config, day);
+
+    log::info!("Starting {} for day {}", config.account, day);
+
+    if config.mode == Mode::Reply {
+        return answer_mentions(config, &localization).await;
+    }
+
+    if config.mode == Mode::MqttState {
+        let history = history::History::open(&config.history_db)?;
+        let now = Utc::now();
+        let today = now.with_timezone(&localization.timezone).date_naive();
+        let tomorrow = today + Duration::days(1);
+        let today_end = get_day_bounds(tomorrow, &localization.timezone).0;
+
+        let mut raw_prices = get_stored_prices(&history, area, today, &localization.timezone)?;
+        raw_prices.extend(get_stored_prices(
+            &history,
+            area,
+            tomorrow,
+            &localization.timezone,
+        )?);
+        let prices = convert_prices(&raw_prices, &localization, &config.vat);
+        let today_prices = prices
+            .iter()
+            .filter(|(ts, _)| *ts < today_end)
+            .cloned()
+            .collect::<Vec<_>>();
+        if today_prices.is_empty() {
+            return Err(format!("No stored prices for {}", today).into());
+        }
+        let aggregates = analyze(&today_prices, config, &localization);
+
+        log::info!("Updating MQTT states");
+        mqtt::publish_state(config, &prices, &aggregates, now).await?;
+        return Ok(());
+    }
+
+    if config.mode == Mode::RenderOnly {
+        log::info!("Fetching prices from entsoe");
+        let raw_prices =
+            fetch_day_prices(&config.entsoe_apikey, area, day, &localization.timezone).await?;
+        render_chart(config, &localization, day, &raw_prices)?;
+        return Ok(());
+    }
+
+    let mut history = history::History::open(&config.history_db)?;
+    if config.mode == Mode::DryRun {
+        history.discard_changes()?;
+    }
+
+    // dry runs need authentication only for checking existing posts
+    let authenticate = config.mode == Mode::Post || !config.skip_exists_check;
+    let mut publishers = Vec::new();
+    // posts to check for corrections, in verification runs and posts that
+    // can be edited in place
+    let mut corrections = Vec::new();
+    for publisher in publisher::get_publishers(config, authenticate).await? {
+        let logged = history
+            .get_posts(&config.account, area, day)?
+            .into_iter()
+            .find(|post| post.publisher == publisher.name());
+        if config.verify {
+            match logged {
+                Some(post) => corrections.push((publisher, post)),
+                None => log::warn!("No post to verify in the post log of {}", publisher.name()),
+            }
+            continue;
+        }
+        if !config.skip_exists_check {
+            if let Some(post) = logged {
+                if publisher.supports_update() {
+                    log::info!(
+                        "Post already exists in the post log of {}, checking for corrections: {}",
+                        publisher.name(),
+                        post.uri
+                    );
+                    corrections.push((publisher, post));
+                } else {
+                    log::info!(
+                        "Post already exists in the post log of {}, skipping: {}",
+                        publisher.name(),
+                        post.uri
+                    );
+                }
+                continue;
+            }
+            if let Some(post) = publisher.find_existing(area, day, &day_title).await? {
+                log::info!(
+                    "Post already exists in {} (matched by {}), skipping: {}",
+                    publisher.name(),
+                    post.matched_by,
+                    post.uri
+                );
+                continue;
+            }
+        }
+        publishers.push(publisher);
+    }
+    if publishers.is_empty() && corrections.is_empty() {
+        return Ok(());
+    }
+
+    log::info!("Fetching prices from entsoe");
+    let raw_prices =
+        fetch_day_prices(&config.entsoe_apikey, area, day, &localization.timezone).await?;
+    let prices = convert_prices(&raw_prices, &localization, &config.vat);
+
+    // the prices may not have been published yet
+    if !(23..=25).contains(&prices.len()) {
+        return Err(format!("Expected 23..25 price points, got {}", prices.len()).into());
+    }
+
+    let resolution = get_resolution(&raw_prices);
+    let series = get_series_record(area, day, &raw_prices, &localization, &config.vat);
+    if !corrections.is_empty() {
+        corrections = pending_corrections(
+            &history,
+            config,
+            &series,
+            &raw_prices,
+            &localization,
+            corrections,
+        )?;
+        if publishers.is_empty() && corrections.is_empty() {
+            return Ok(());
+        }
+    }
+    // with corrections to make, the prices are saved once they've succeeded
+    if corrections.is_empty() {
+        log::info!("Updating price history");
+        history.save_series(&series, &raw_prices)?;
+    }
+
+    // today's prices are only needed for comparing to today, which is left
+    // out if they can't be fetched
+    let today = day - Duration::days(1);
+    if history
+        .get_series(area, today, resolution.num_minutes())?
+        .is_none()
+    {
+        log::info!("Fetching prices for {} from entsoe", today);
+        match fetch_day_prices(&config.entsoe_apikey, area, today, &localization.timezone).await {
+            Ok(today_prices) if !today_prices.is_empty() => history.save_series(
+                &get_series_record(area, today, &today_prices, &localization, &config.vat),
+                &today_prices,
+            )?,
+            Ok(_) => log::warn!("No prices for {}, not comparing to today", today),
+            Err(err) => log::warn!(
+                "Fetching prices for {} failed, not comparing to today: {}",
+                today,
+                err.without_url()
+            ),
+        }
+    }
+
+    let mut aggregates = analyze(&prices, config, &localization);
+    aggregates.today_avg = get_history_average(
+        &history,
+        area,
+        resolution,
+        (today, day),
+        &localization,
+        config.vat.negative_prices,
+    )?;
+    aggregates.last_week_avg = get_history_average(
+        &history,
+        area,
+        resolution,
+        (day - Duration::days(7), day - Duration::days(6)),
+        &localization,
+        config.vat.negative_prices,
+    )?;
+    aggregates.rolling_30d_avg = get_history_average(
+        &history,
+        area,
+        resolution,
+        (day - Duration::days(30), day),
+        &localization,
+        config.vat.negative_prices,
+    )?;
+
+    log::info!("Plotting graph");
+    plotter::plot(
+        &plot_filename,
+        &prices,
+        &aggregates,
+        &day_title,
+        &localization,
+        vat_rate,
+    )?;
+
+    let report = publisher::DailyReport {
+        area: area.clone(),
+        day,
+        day_title: day_title.clone(),
+        prices: prices.clone(),
+        unit: format!("{}/kWh", localization.currency_name),
+        aggregates: aggregates.clone(),
+        raw_prices: raw_prices.clone(),
+        vat: vat_rate,
+        blocks: poster::compose_blocks(
+            &aggregates,
+            &localization,
+            &day_title,
+            &config.statistics,
+            vat_rate,
+            config.vat.negative_prices == vat::NegativePricePolicy::Exempt
+                && aggregates.min.1 < 0.0,
+        ),
+        link: config.post_link.clone(),
+        tags: config.post_tags.clone(),
+        image: std::fs::read(&plot_filename)?,
+        image_filename: plot_filename.clone(),
+        alt_text: poster::compose_alt_text(&prices, &aggregates, &localization, &day_title),
+        price_table: poster::compose_price_table(&prices, &localization, &day_title),
+        correction_title: format!("{} {}", localization.post_correction, day_title),
+        revision: None,
+    };
+
+    for publisher in publishers {
+        if config.mode == Mode::DryRun {
+            log::info!(
+                "Dry run of {}:\n{}\n",
+                publisher.name(),
+                publisher.preview(&report).await?
+            );
+            continue;
+        }
+
+        log::info!("Publishing to {}", publisher.name());
+        let uri = publisher.publish(&report).await?;
+
+        history.save_post(&history::PostRecord {
+            account: config.account.clone(),
+            area: area.clone(),
+            day,
+            publisher: publisher.name().to_string(),
+            uri,
+            posted_at: Utc::now(),
+            revision: Some(publisher::prices_revision(&raw_prices)),
+        })?;
+    }
+    if !corrections.is_empty() {
+        correct_posts(config, &mut history, corrections, &report, &series).await?;
+    }
+    if config.mode == Mode::DryRun {
+        log::info!("Image: {}", report.image_filename);
+    }
+
+    Ok(())
+}
+
+// a publisher and its logged post of the day
+type LoggedPost = (Box<dyn Publisher>, history::PostRecord);
+
+// The logged posts to correct, if the prices differ from the stored ones by
+// at least the correction threshold. Posts already showing the prices are
+// left out, e.g. ones corrected in a run where other corrections failed.
+fn pending_corrections(
+    history: &history::History,
+    config: &Config,
+    series: &history::SeriesRecord,
+    raw_prices: &[Price],
+    localization: &Localization,
+    corrections: Vec<LoggedPost>,
+) -> Result<Vec<LoggedPost>, Box<dyn std::error::Error>> {
+    let (day_start, day_end) = get_day_bounds(series.day, &localization.timezone);
+    let stored_prices =
+        history.get_prices(&series.area, series.resolution_minutes, day_start, day_end)?;
+    if stored_prices.is_empty() {
+        log::info!(
+            "No stored prices for {} to compare with, not correcting",
+            series.day
+        );
+        return Ok(Vec::new());
+    }
+
+    let difference = get_max_difference(&stored_prices, raw_prices);
+    if difference < config.correction_threshold {
+        log::info!(
+            "Prices have not changed materially, largest difference {:.2} €/MWh",
+            difference
+        );
+        return Ok(Vec::new());
+    }
+    log::info!(
+        "Prices have been corrected, largest difference {:.2} €/MWh",
+        difference
+    );
+
+    let revision = publisher::prices_revision(raw_prices);
+    Ok(corrections
+        .into_iter()
+        .filter(|(publisher, post)| {
+            let corrected = post.revision.as_ref() == Some(&revision);
+            if corrected {
+                log::info!(
+                    "Post in {} already corrected: {}",
+                    publisher.name(),
+                    post.uri
+                );
+            }
+            !corrected
+        })
+        .collect())
+}
+
+// Corrects the posts and saves the corrected prices. If any correction
+// fails, the prices aren't saved, so that the next run tries again.
+async fn correct_posts(
+    config: &Config,
+    history: &mut history::History,
+    corrections: Vec<LoggedPost>,
+    report: &publisher::DailyReport,
+    series: &history::SeriesRecord,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let revision = publisher::prices_revision(&report.raw_prices);
+    let mut failed = Vec::new();
+    for (publisher, post) in corrections {
+        let uri = post.uri;
+        let action = if publisher.supports_update() {
+            "editing"
+        } else {
+            match config.correction_policy {
+                config::CorrectionPolicy::Reply => "replying to",
+                config::CorrectionPolicy::Repost => "deleting and re-posting",
+            }
+        };
+        if config.mode == Mode::DryRun {
+            log::info!(
+                "Dry run of correcting {} in {} by {} it:\n{}\n",
+                uri,
+                publisher.name(),
+                action,
+                publisher.preview(&report.correction()).await?
+            );
+            continue;
+        }
+
+        log::info!(
+            "Correcting {} in {} by {} it",
+            uri,
+            publisher.name(),
+            action
+        );
+        let result = if publisher.supports_update() {
+            publisher.update(&uri, report).await
+        } else {
+            match config.correction_policy {
+                config::CorrectionPolicy::Reply => {
+                    publisher.reply(&uri, &report.correction()).await
+                }
+                config::CorrectionPolicy::Repost => match publisher.delete(&uri).await {
+                    Ok(()) => publisher.publish(&report.revised()).await,
+                    Err(err) => Err(err),
+                },
+            }
+        };
+        // the other posts are still corrected if one fails
+        let uri = match result {
+            Ok(uri) => uri,
+            Err(err) => {
+                log::error!("Could not correct {} in {}: {}", uri, publisher.name(), err);
+                failed.push(publisher.name());
+                continue;
+            }
+        };
+
+        history.save_post(&history::PostRecord {
+            account: config.account.clone(),
+            area: report.area.clone(),
+            day: report.day,
+            publisher: publisher.name().to_string(),
+            uri,
+            posted_at: Utc::now(),
+            revision: Some(revision.clone()),
+        })?;
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "Correcting posts failed in {}, trying again on the next run",
+            failed.join(", ")
+        )
+        .into());
+    }
+    log::info!("Updating price history");
+    history.save_series(series, &report.raw_prices)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::localization::tests::localization;
+    use crate::publisher::tests::sample_report;
+    use crate::publisher::{DailyReport, ExistingPost};
+    use async_trait::async_trait;
+
+    // edits posts in place, or fails to
+    struct EditingPublisher {
+        name: &'static str,
+        fail: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl Publisher for EditingPublisher {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn find_existing(
+            &self,
+            _area: &str,
+            _day: NaiveDate,
+            _day_title: &str,
+        ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+            Ok(None)
+        }
+
+        async fn preview(
+            &self,
+            _report: &DailyReport,
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            Ok(String::new())
+        }
+
+        async fn publish(
+            &self,
+            _report: &DailyReport,
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            Ok(format!("{}/post", self.name))
+        }
+
+        fn supports_update(&self) -> bool {
+            true
+        }
+
+        async fn update(
+            &self,
+            uri: &str,
+            _report: &DailyReport,
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            match self.fail {
+                true => Err("Service unavailable".into()),
+                false => Ok(format!("{}/edited", uri)),
+            }
+        }
+    }
+
+    // the publisher with its post in the post log
+    fn logged_post(history: &history::History, name: &'static str, fail: bool) -> LoggedPost {
+        let report = sample_report();
+        let post = history
+            .get_posts(&config(&[]).account, &report.area, report.day)
+            .unwrap()
+            .into_iter()
+            .find(|post| post.publisher == name)
+            .unwrap();
+        (Box::new(EditingPublisher { name, fail }), post)
+    }
+
+    #[tokio::test]
+    async fn failed_corrections_are_tried_again() {
+        let config = config(&[]);
+        let localization = localization(chrono::Locale::fi_FI);
+        let mut history = history::History::open(":memory:").unwrap();
+        let posted = sample_report();
+        let series = get_series_record(
+            &posted.area,
+            posted.day,
+            &posted.raw_prices,
+            &localization,
+            &config.vat,
+        );
+        history.save_series(&series, &posted.raw_prices).unwrap();
+        for name in ["telegram", "webhook"] {
+            history
+                .save_post(&history::PostRecord {
+                    account: config.account.clone(),
+                    area: posted.area.clone(),
+                    day: posted.day,
+                    publisher: name.to_string(),
+                    uri: name.to_string(),
+                    posted_at: Utc::now(),
+                    revision: Some(publisher::prices_revision(&posted.raw_prices)),
+                })
+                .unwrap();
+        }
+
+        let mut corrected = sample_report();
+        corrected.raw_prices[5].1 += 10.0;
+        let pending = |history: &history::History, fail: bool| {
+            pending_corrections(
+                history,
+                &config,
+                &series,
+                &corrected.raw_prices,
+                &localization,
+                vec![
+                    logged_post(history, "telegram", false),
+                    logged_post(history, "webhook", fail),
+                ],
+            )
+            .unwrap()
+        };
+
+        // one of the corrections fails, the corrected prices aren't saved
+        let corrections = pending(&history, true);
+        assert_eq!(corrections.len(), 2);
+        let error = correct_posts(&config, &mut history, corrections, &corrected, &series)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("webhook"));
+
+        // the next run corrects only the failed post and saves the prices
+        let corrections = pending(&history, false);
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].0.name(), "webhook");
+        correct_posts(&config, &mut history, corrections, &corrected, &series)
+            .await
+            .unwrap();
+        assert!(pending(&history, false).is_empty());
+
+        let posts = history
+            .get_posts(&config.account, &posted.area, posted.day)
+            .unwrap();
+        assert_eq!(posts.len(), 2);
+        assert!(posts.iter().all(|post| post.uri.ends_with("/edited")
+            && post.revision == Some(publisher::prices_revision(&corrected.raw_prices))));
+    }
+
+    #[test]
+    fn no_chart_without_prices() {
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        let result = render_chart(&config(&[]), &localization(chrono::Locale::fi_FI), day, &[]);
+        assert_eq!(result.unwrap_err().to_string(), "No prices for 2025-01-02");
+    }
+}
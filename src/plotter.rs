@@ -3,11 +3,12 @@ use std::ops::Range;
 use chrono::{DateTime, Timelike, Utc};
 use plotters::{define_color, doc, prelude::*};
 
-use crate::{Aggregates, Localization};
+use crate::{compressor, Aggregates, Localization};
 
 define_color!(NORMAL, 211, 210, 71, "Normal price color");
 define_color!(HIGH, 211, 186, 71, "High");
 define_color!(LOW, 155, 197, 66, "Low");
+define_color!(CHEAPEST, 71, 160, 211, "Cheapest window or slots");
 
 fn color_by_value(value: f32, high: f32, low: f32) -> ShapeStyle {
     if value > high {
@@ -54,6 +55,7 @@ pub fn plot(
     aggregates: &Aggregates,
     title: &String,
     localization: &Localization,
+    vat: f32,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let root = BitMapBackend::new(filename, (1024, 1024)).into_drawing_area();
     root.fill(&WHITE)?;
@@ -69,11 +71,22 @@ pub fn plot(
 
     let label_style = ("sans-serif", 25).into_font();
 
+    let y_desc = if vat > 0.0 {
+        format!(
+            "{} ({} {} %)",
+            localization.plot_y_desc,
+            localization.plot_vat,
+            format!("{:.1}", vat).replace(".", localization.num_locale.decimal())
+        )
+    } else {
+        localization.plot_y_desc.to_string()
+    };
+
     chart
         .configure_mesh()
         .label_style(label_style.clone())
         .axis_desc_style(label_style)
-        .y_desc(localization.plot_y_desc)
+        .y_desc(y_desc)
         .x_desc(localization.plot_x_desc)
         .x_labels(24)
         .y_labels(10)
@@ -81,15 +94,27 @@ pub fn plot(
         .y_label_formatter(&|y| format!("{:.0}", y))
         .draw()?;
 
+    let highlighted = [&aggregates.cheapest_window, &aggregates.cheapest_slots]
+        .into_iter()
+        .flatten()
+        .flat_map(|cheapest| cheapest.slots.iter().map(|(ts, _)| *ts))
+        .collect::<Vec<_>>();
+
     chart.draw_series(data.iter().map(|(ts, value)| {
         let x = ts.with_timezone(&localization.timezone).hour() as i32;
 
+        let style = if highlighted.contains(ts) {
+            CHEAPEST.filled()
+        } else {
+            color_by_value(*value, high, low)
+        };
+
         let mut bar = Rectangle::new(
             [
                 (SegmentValue::Exact(x), 0.0),
                 (SegmentValue::Exact(x + 1), *value),
             ],
-            color_by_value(*value, high, low),
+            style,
         );
         bar.set_margin(0, 0, 2, 2);
         bar
@@ -99,3 +124,48 @@ pub fn plot(
 
     Ok(())
 }
+
+// Bluesky's recommended 3:1 profile banner
+static BANNER_SIZE: (u32, u32) = (1500, 500);
+
+// Bars of the day's prices without axes or labels, with the cheapest slots
+// highlighted. Returns the image as PNG.
+pub fn plot_sparkline(
+    data: &[(DateTime<Utc>, f32)],
+    aggregates: &Aggregates,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (width, height) = BANNER_SIZE;
+    let mut buffer = vec![0; (width * height * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, BANNER_SIZE).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let y_max = aggregates.max.1.max(0.0) * 1.1 + 0.1;
+        let y_min = aggregates.min.1.min(0.0) * 1.1;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(20)
+            .build_cartesian_2d(0..data.len() as i32, y_min..y_max)?;
+
+        let highlighted = [&aggregates.cheapest_window, &aggregates.cheapest_slots]
+            .into_iter()
+            .flatten()
+            .flat_map(|cheapest| cheapest.slots.iter().map(|(ts, _)| *ts))
+            .collect::<Vec<_>>();
+
+        chart.draw_series(data.iter().enumerate().map(|(i, (ts, value))| {
+            let style = if highlighted.contains(ts) {
+                CHEAPEST.filled()
+            } else {
+                NORMAL.filled()
+            };
+            let mut bar = Rectangle::new([(i as i32, 0.0), (i as i32 + 1, *value)], style);
+            bar.set_margin(0, 0, 2, 2);
+            bar
+        }))?;
+
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(width, height, buffer).ok_or("Invalid banner buffer")?;
+    compressor::encode_png(&image::DynamicImage::ImageRgb8(image))
+}
@@ -1,151 +1,586 @@
-use bsky_sdk::{
-    api::{
-        app::bsky::{
-            embed::images::{ImageData, MainData},
-            feed::{
-                get_author_feed::ParametersData,
-                post::{RecordData, RecordEmbedRefs},
-            },
-        },
-        com::atproto::server::create_session::OutputData,
-        types::{string::Datetime, Object, Union, Unknown},
-    },
-    BskyAgent,
-};
 use chrono::{DateTime, Duration, Timelike, Utc};
-use chrono_tz::Europe::Helsinki;
-
-use crate::{Aggregates, Localization};
-
-pub async fn check_post_exists(
-    agent: &BskyAgent,
-    session: &Object<OutputData>,
-    title: &String,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let own_posts_feed = agent
-        .api
-        .app
-        .bsky
-        .feed
-        .get_author_feed(
-            ParametersData {
-                actor: session.data.handle.clone().into(),
-                cursor: None,
-                filter: None,
-                include_pins: None,
-                limit: Some(5.try_into().unwrap()),
-            }
-            .into(),
-        )
-        .await?;
+use chrono_tz::Tz;
 
-    let posts_with_date =
-        own_posts_feed
-            .data
-            .feed
-            .iter()
-            .find(|record| match &record.data.post.data.record {
-                Unknown::Object(r) => {
-                    match r.get_key_value("text") {
-                        Some((_, x)) => {
-                            if format!("{:?}", x).contains(title) {
-                                return true;
-                            }
-                        }
-                        _ => {}
-                    };
-                    false
-                }
-                _ => false,
-            });
+use crate::statistics::merge_slots;
+use crate::{get_resolution, Aggregates, CheapestSlots, Localization, Statistic};
+
+fn format_time(ts: DateTime<Tz>) -> String {
+    if ts.minute() == 0 {
+        format!("{:02}", ts.hour())
+    } else {
+        format!("{:02}:{:02}", ts.hour(), ts.minute())
+    }
+}
 
-    Ok(posts_with_date.is_some())
+// a number with the locale's decimal separator, the rest of the text is left as is
+pub fn format_number(value: f32, decimals: usize, localization: &Localization) -> String {
+    format!("{:.*}", decimals, value).replace('.', localization.num_locale.decimal())
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration.num_minutes() % 60 == 0 {
+        format!("{} h", duration.num_hours())
+    } else {
+        format!("{} min", duration.num_minutes())
+    }
+}
+
+// consecutive slots are merged into a single range, e.g. "01-03, 05-06"
+pub fn format_timeranges(
+    slots: &[(DateTime<Utc>, f32)],
+    resolution: Duration,
+    timezone: &Tz,
+) -> String {
+    merge_slots(slots, resolution)
+        .iter()
+        .map(|(start, end)| {
+            format!(
+                "{}-{}",
+                format_time(start.with_timezone(timezone)),
+                format_time(end.with_timezone(timezone))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-pub async fn post(
-    agent: &BskyAgent,
-    image_filename: &str,
+pub fn format_cheapest(
+    label: &str,
+    cheapest: Option<&CheapestSlots>,
+    localization: &Localization,
+) -> Option<String> {
+    cheapest.map(|cheapest| {
+        format!(
+            "{} {}: {} {} ({} {}/kWh)",
+            label,
+            format_duration(cheapest.length),
+            localization.post_at,
+            format_timeranges(&cheapest.slots, cheapest.resolution, &localization.timezone),
+            format_number(cheapest.avg, 2, localization),
+            localization.currency_name
+        )
+    })
+}
+
+// how a statistic is shown in the post
+enum StatisticValue {
+    Price(f32),
+    PriceAt(f32, DateTime<Utc>),
+    // change of the average compared to a reference average
+    ChangeFrom(f32),
+}
+
+fn format_statistic(
+    statistic: Statistic,
     aggregates: &Aggregates,
     localization: &Localization,
-    day_title: &String,
-    vat: f32,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let image_bytes = std::fs::read(image_filename)?;
+) -> Option<String> {
+    let timerange = |ts: DateTime<Utc>| {
+        format_timeranges(&[(ts, 0.0)], Duration::hours(1), &localization.timezone)
+    };
+
+    // e.g. "Peak (Mon-Fri 08-20)"
+    let peak_label = format!(
+        "{} ({} {:02}-{:02})",
+        localization.post_peak,
+        localization.post_weekdays,
+        aggregates.peak_hours.from,
+        aggregates.peak_hours.to
+    );
 
-    let output = agent.api.com.atproto.repo.upload_blob(image_bytes).await?;
+    let (label, value) = match statistic {
+        Statistic::Avg => (localization.post_avg, StatisticValue::Price(aggregates.avg)),
+        Statistic::Min => (
+            localization.post_min,
+            StatisticValue::PriceAt(aggregates.min.1, aggregates.min.0),
+        ),
+        Statistic::Max => (
+            localization.post_max,
+            StatisticValue::PriceAt(aggregates.max.1, aggregates.max.0),
+        ),
+        Statistic::Median => (
+            localization.post_median,
+            StatisticValue::Price(aggregates.median),
+        ),
+        Statistic::P10 => (localization.post_p10, StatisticValue::Price(aggregates.p10)),
+        Statistic::P90 => (localization.post_p90, StatisticValue::Price(aggregates.p90)),
+        Statistic::Base => (
+            localization.post_base,
+            StatisticValue::Price(aggregates.base),
+        ),
+        Statistic::Peak => (peak_label.as_str(), StatisticValue::Price(aggregates.peak?)),
+        Statistic::OffPeak => (
+            localization.post_off_peak,
+            StatisticValue::Price(aggregates.off_peak?),
+        ),
+        Statistic::Spread => (
+            localization.post_spread,
+            StatisticValue::Price(aggregates.spread),
+        ),
+        Statistic::StdDev => (
+            localization.post_std_dev,
+            StatisticValue::Price(aggregates.std_dev),
+        ),
+        Statistic::VsToday => (
+            localization.post_vs_today,
+            StatisticValue::ChangeFrom(aggregates.today_avg?),
+        ),
+        Statistic::VsLastWeek => (
+            localization.post_vs_last_week,
+            StatisticValue::ChangeFrom(aggregates.last_week_avg?),
+        ),
+        Statistic::Vs30Days => (
+            localization.post_vs_30d,
+            StatisticValue::ChangeFrom(aggregates.rolling_30d_avg?),
+        ),
+    };
 
-    let image_data = ImageData {
-        alt: "".to_string(),
-        aspect_ratio: None,
-        image: output.data.blob,
+    let text = match value {
+        StatisticValue::Price(price) => format!(
+            "{}: {} {}/kWh",
+            label,
+            format_number(price, 2, localization),
+            localization.currency_name
+        ),
+        StatisticValue::PriceAt(price, ts) => format!(
+            "{}: {} {}/kWh ({} {})",
+            label,
+            format_number(price, 2, localization),
+            localization.currency_name,
+            localization.post_at,
+            timerange(ts)
+        ),
+        StatisticValue::ChangeFrom(reference) => format!(
+            "{}: {}",
+            label,
+            format_change(aggregates.avg, reference, localization)
+        ),
     };
 
-    let images = vec![image_data.into()];
+    Some(text)
+}
 
-    let timerange = |ts: DateTime<Utc>| {
-        let ts1 = ts.with_timezone(&Helsinki);
-        let ts2 = ts1 + Duration::hours(1);
-        format!("{:02}-{:02}", ts1.hour(), ts2.hour())
+// change of the average compared to a reference average, e.g. "↑ +0.52 c/kWh (+12 %)"
+fn format_change(avg: f32, reference: f32, localization: &Localization) -> String {
+    let diff = avg - reference;
+    let arrow = if diff > 0.005 {
+        "↑"
+    } else if diff < -0.005 {
+        "↓"
+    } else {
+        "→"
+    };
+    // percentages are meaningless for references close to zero or negative
+    let percentage = if reference > 0.1 {
+        format!(" ({:+.0} %)", diff / reference * 100.0)
+    } else {
+        "".to_string()
     };
 
-    let text_header = format!("{} {}", localization.post_title, day_title);
-    let text_avg = format!(
-        "{}: {:.2} {}/kWh",
-        localization.post_avg, aggregates.avg, localization.currency_name
-    )
-    .replace(".", localization.num_locale.decimal());
-    let text_min = format!(
-        "{}: {:.2} {}/kWh ({} {})",
-        localization.post_min,
-        aggregates.min.1,
-        localization.currency_name,
-        localization.post_at,
-        timerange(aggregates.min.0)
-    )
-    .replace(".", localization.num_locale.decimal());
-    let text_max = format!(
-        "{}: {:.2} {}/kWh ({} {})",
-        localization.post_max,
-        aggregates.max.1,
+    format!(
+        "{} {}{} {}/kWh{}",
+        arrow,
+        if diff >= 0.0 { "+" } else { "" },
+        format_number(diff, 2, localization),
         localization.currency_name,
-        localization.post_at,
-        timerange(aggregates.max.0)
+        percentage
     )
-    .replace(".", localization.num_locale.decimal());
+}
 
-    let text_vat = if vat > 0.0 {
-        format!(
-            "\n\n{} {} %",
+// post text as blocks separated by empty lines: header, statistics and VAT
+pub fn compose_blocks(
+    aggregates: &Aggregates,
+    localization: &Localization,
+    day_title: &String,
+    statistics: &[Statistic],
+    vat: f32,
+    negative_prices_exempt: bool,
+) -> Vec<String> {
+    let mut blocks = vec![format!("{} {}", localization.post_title, day_title)];
+
+    let text_statistics = statistics
+        .iter()
+        .filter_map(|statistic| format_statistic(*statistic, aggregates, localization))
+        .chain(format_cheapest(
+            localization.post_cheapest_window,
+            aggregates.cheapest_window.as_ref(),
+            localization,
+        ))
+        .chain(format_cheapest(
+            localization.post_cheapest_slots,
+            aggregates.cheapest_slots.as_ref(),
+            localization,
+        ))
+        .collect::<Vec<_>>()
+        .join("\n");
+    blocks.push(text_statistics);
+
+    if vat > 0.0 {
+        blocks.push(format!(
+            "{} {} %{}",
             localization.post_vat,
-            format!("{:.1}", vat).replace(".", localization.num_locale.decimal())
-        )
-    } else {
-        "".to_string()
+            format_number(vat, 1, localization),
+            if negative_prices_exempt {
+                format!("\n{}", localization.post_vat_negative)
+            } else {
+                "".to_string()
+            }
+        ));
+    }
+
+    blocks
+}
+
+// chart description for screen readers
+#[derive(Clone, Debug)]
+pub struct AltText {
+    // shape of the day and aggregates
+    pub summary: String,
+    pub hourly: String,
+}
+
+impl AltText {
+    // summary with the hourly prices if they fit within max_length characters
+    pub fn fit(&self, max_length: usize) -> String {
+        if self.summary.chars().count() + self.hourly.chars().count() <= max_length {
+            format!("{}{}", self.summary, self.hourly)
+        } else {
+            self.summary.clone()
+        }
+    }
+}
+
+pub fn compose_alt_text(
+    prices: &[(DateTime<Utc>, f32)],
+    aggregates: &Aggregates,
+    localization: &Localization,
+    day_title: &String,
+) -> AltText {
+    let local_hour = |ts: &DateTime<Utc>| ts.with_timezone(&localization.timezone).hour();
+
+    // averages of the six hour parts of the day, starting from midnight
+    let mut parts = [(0.0, 0); 4];
+    for (ts, price) in prices {
+        let part = &mut parts[(local_hour(ts) / 6) as usize];
+        *part = (part.0 + price, part.1 + 1);
+    }
+    let part_avgs = parts
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(i, (sum, count))| (i, sum / *count as f32))
+        .collect::<Vec<_>>();
+    let cheapest_part = part_avgs
+        .iter()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    let most_expensive_part = part_avgs
+        .iter()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+
+    let text_shape = match (cheapest_part, most_expensive_part) {
+        // nothing to tell about a flat day
+        (Some((cheapest, min)), Some((most_expensive, max))) if min < max => format!(
+            " {} {}, {} {}.",
+            localization.alt_cheapest,
+            localization.alt_parts_of_day[*cheapest],
+            localization.alt_most_expensive,
+            localization.alt_parts_of_day[*most_expensive]
+        ),
+        _ => "".to_string(),
     };
 
-    let text = format!(
-        "{}\n\n{}\n{}\n{}{}",
-        text_header, text_avg, text_min, text_max, text_vat
+    let text_aggregates = [Statistic::Avg, Statistic::Min, Statistic::Max]
+        .iter()
+        .filter_map(|statistic| format_statistic(*statistic, aggregates, localization))
+        .collect::<Vec<_>>()
+        .join(". ");
+
+    // hourly averages keep the list compact also for sub-hour resolutions
+    let mut hours: Vec<(u32, f32, usize)> = Vec::new();
+    for (ts, price) in prices {
+        let hour = local_hour(ts);
+        match hours.last_mut() {
+            Some((last, sum, count)) if *last == hour => {
+                *sum += price;
+                *count += 1;
+            }
+            _ => hours.push((hour, *price, 1)),
+        }
+    }
+    let text_hourly = format!(
+        " {}: {}.",
+        localization.alt_hourly,
+        hours
+            .iter()
+            .map(|(hour, sum, count)| {
+                format!(
+                    "{:02} {}",
+                    hour,
+                    format_number(sum / *count as f32, 1, localization)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
     );
 
-    let embed = Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(
-        Box::new(MainData { images }.into()),
-    )));
-
-    println!("Posting: {}", text);
-
-    agent
-        .create_record(RecordData {
-            created_at: Datetime::now(),
-            embed,
-            entities: None,
-            facets: None,
-            labels: None,
-            langs: None,
-            reply: None,
-            tags: None,
-            text,
-        })
-        .await?;
-    Ok(())
+    let summary = format!(
+        "{} {}.{} {}.",
+        localization.alt_chart, day_title, text_shape, text_aggregates
+    );
+
+    AltText {
+        summary,
+        hourly: text_hourly,
+    }
+}
+
+// every slot's price, posted as replies to the main post
+#[derive(Clone, Debug)]
+pub struct PriceTable {
+    pub title: String,
+    // one line per slot, e.g. "18-19: 2,81"
+    pub rows: Vec<String>,
+}
+
+impl PriceTable {
+    // Posts of whole rows within max_length as measured by length, each
+    // starting with the title and numbered if there are several.
+    pub fn split(&self, max_length: usize, length: impl Fn(&str) -> usize) -> Vec<String> {
+        // room for the title with numbering, e.g. " (2/3)", and a line break
+        let title_length = length(&self.title) + length(" (99/99)") + 1;
+        let mut bodies: Vec<String> = Vec::new();
+        for row in &self.rows {
+            match bodies.last_mut() {
+                Some(body) if title_length + length(body) + 1 + length(row) <= max_length => {
+                    body.push('\n');
+                    body.push_str(row);
+                }
+                _ => bodies.push(row.clone()),
+            }
+        }
+
+        let count = bodies.len();
+        bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                if count > 1 {
+                    format!("{} ({}/{})\n{}", self.title, i + 1, count, body)
+                } else {
+                    format!("{}\n{}", self.title, body)
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn compose_price_table(
+    prices: &[(DateTime<Utc>, f32)],
+    localization: &Localization,
+    day_title: &String,
+) -> PriceTable {
+    let resolution = get_resolution(prices);
+    PriceTable {
+        title: format!(
+            "{} {} ({}/kWh)",
+            localization.alt_hourly, day_title, localization.currency_name
+        ),
+        rows: prices
+            .iter()
+            .map(|(ts, price)| {
+                // start times keep sub-hour rows short
+                let time = if resolution < Duration::hours(1) {
+                    format_time(ts.with_timezone(&localization.timezone))
+                } else {
+                    format_timeranges(&[(*ts, *price)], resolution, &localization.timezone)
+                };
+                format!("{}: {}", time, format_number(*price, 2, localization))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::tests::localization;
+    use crate::publisher::count_graphemes;
+    use crate::publisher::tests::sample_report;
+
+    #[test]
+    fn statistics_have_locale_decimals() {
+        let localization = localization(chrono::Locale::fi_FI);
+        let aggregates = sample_report().aggregates;
+        let line = |statistic| format_statistic(statistic, &aggregates, &localization).unwrap();
+
+        assert_eq!(line(Statistic::P10), "10. persentiili: 2,23 c/kWh");
+        assert_eq!(line(Statistic::P90), "90. persentiili: 4,07 c/kWh");
+        assert_eq!(line(Statistic::Base), "Perushinta: 3,15 c/kWh");
+        assert_eq!(
+            line(Statistic::Peak),
+            "Huippuhinta (ma-pe 08-20): 3,35 c/kWh"
+        );
+        assert_eq!(line(Statistic::Max), "Maksimi: 4,30 c/kWh (klo 23-00)");
+    }
+
+    #[test]
+    fn comparisons_keep_their_labels() {
+        let mut aggregates = sample_report().aggregates;
+        aggregates.avg = 3.0;
+        aggregates.today_avg = Some(2.5);
+        aggregates.last_week_avg = Some(4.0);
+
+        let finnish = localization(chrono::Locale::fi_FI);
+        assert_eq!(
+            format_statistic(Statistic::VsToday, &aggregates, &finnish).unwrap(),
+            "Vs. tänään: ↑ +0,50 c/kWh (+20 %)"
+        );
+        let estonian = localization(chrono::Locale::et_EE);
+        assert_eq!(
+            format_statistic(Statistic::VsLastWeek, &aggregates, &estonian).unwrap(),
+            "Vs. week ago: ↓ -1,00 c/kWh (-25 %)"
+        );
+        // left out without a reference
+        assert!(format_statistic(Statistic::Vs30Days, &aggregates, &estonian).is_none());
+    }
+
+    #[test]
+    fn changes_have_arrows_and_percentages() {
+        let localization = localization(chrono::Locale::fi_FI);
+        let change = |avg, reference| format_change(avg, reference, &localization);
+
+        assert_eq!(change(3.0, 2.5), "↑ +0,50 c/kWh (+20 %)");
+        assert_eq!(change(2.0, 2.5), "↓ -0,50 c/kWh (-20 %)");
+        // differences below half a cent are no change
+        assert_eq!(change(2.504, 2.5), "→ +0,00 c/kWh (+0 %)");
+        assert_eq!(change(2.496, 2.5), "→ -0,00 c/kWh (-0 %)");
+        assert_eq!(change(2.51, 2.5), "↑ +0,01 c/kWh (+0 %)");
+        // no percentages of references close to zero or negative
+        assert_eq!(change(1.0, 0.05), "↑ +0,95 c/kWh");
+        assert_eq!(change(1.0, -1.0), "↑ +2,00 c/kWh");
+    }
+
+    #[test]
+    fn alt_text_tells_the_shape_of_the_day() {
+        let report = sample_report();
+        let alt_text = &report.alt_text;
+        assert_eq!(
+            alt_text.summary,
+            format!(
+                "Pylväskaavio pörssisähkön tuntihinnoista {}. Halvinta yöllä, kalleinta illalla. \
+                 Keskiarvo: 3,15 c/kWh. Minimi: 2,00 c/kWh (klo 00-01). \
+                 Maksimi: 4,30 c/kWh (klo 23-00).",
+                report.day_title
+            )
+        );
+        assert!(alt_text
+            .hourly
+            .starts_with(" Tuntihinnat: 00 2,0; 01 2,1; 02 2,2;"));
+        assert!(alt_text.hourly.ends_with("; 23 4,3."));
+
+        // a flat day has no cheapest or most expensive part
+        let localization = localization(chrono::Locale::fi_FI);
+        let prices: Vec<_> = report.prices.iter().map(|(ts, _)| (*ts, 3.0)).collect();
+        let alt_text = compose_alt_text(
+            &prices,
+            &report.aggregates,
+            &localization,
+            &report.day_title,
+        );
+        assert!(!alt_text.summary.contains("Halvinta"));
+    }
+
+    #[test]
+    fn alt_text_averages_sub_hour_prices() {
+        let localization = localization(chrono::Locale::fi_FI);
+        let report = sample_report();
+        let start = report.prices[0].0;
+        // quarters of each hour average to the hour plus 3
+        let prices: Vec<_> = (0..96)
+            .map(|i| {
+                let quarter = [1.0, 2.0, 3.0, 6.0][i % 4];
+                (
+                    start + Duration::minutes(15 * i as i64),
+                    (i / 4) as f32 + quarter,
+                )
+            })
+            .collect();
+        let aggregates = crate::calculate_aggregates(
+            &prices,
+            &localization.timezone,
+            crate::HourRange { from: 8, to: 20 },
+        );
+
+        let alt_text = compose_alt_text(&prices, &aggregates, &localization, &report.day_title);
+        let hours: Vec<_> = alt_text
+            .hourly
+            .trim_start_matches(" Tuntihinnat: ")
+            .trim_end_matches('.')
+            .split("; ")
+            .collect();
+        assert_eq!(hours.len(), 24);
+        assert_eq!(hours[0], "00 3,0");
+        assert_eq!(hours[12], "12 15,0");
+        assert_eq!(hours[23], "23 26,0");
+    }
+
+    #[test]
+    fn alt_text_drops_the_hourly_prices_that_dont_fit() {
+        let alt_text = sample_report().alt_text;
+        let full = alt_text.summary.chars().count() + alt_text.hourly.chars().count();
+
+        assert_eq!(
+            alt_text.fit(full),
+            format!("{}{}", alt_text.summary, alt_text.hourly)
+        );
+        assert_eq!(alt_text.fit(full - 1), alt_text.summary);
+        // the limit counts characters, not bytes
+        assert!(alt_text.fit(full).len() > full);
+    }
+
+    #[test]
+    fn price_table_is_split_at_the_limit() {
+        let table = sample_report().price_table;
+        assert_eq!(table.title, "Tuntihinnat torstai 02.01.2025 (c/kWh)");
+        assert_eq!(table.rows[0], "00-01: 2,00");
+
+        let single = table.split(1000, count_graphemes);
+        assert_eq!(single.len(), 1);
+        assert_eq!(
+            single[0],
+            format!("{}\n{}", table.title, table.rows.join("\n"))
+        );
+
+        // exactly eight rows fit with the numbered title
+        let max_length = count_graphemes(&format!(
+            "{} (99/99)\n{}",
+            table.title,
+            table.rows[..8].join("\n")
+        ));
+        let posts = table.split(max_length, count_graphemes);
+        assert_eq!(posts.len(), 3);
+        for (i, post) in posts.iter().enumerate() {
+            assert!(count_graphemes(post) <= max_length);
+            let (title, rows) = post.split_once('\n').unwrap();
+            assert_eq!(title, format!("{} ({}/3)", table.title, i + 1));
+            assert_eq!(rows, table.rows[i * 8..(i + 1) * 8].join("\n"));
+        }
+        assert_eq!(table.split(max_length - 1, count_graphemes).len(), 4);
+    }
+
+    #[test]
+    fn sub_hour_rows_show_start_times() {
+        let localization = localization(chrono::Locale::fi_FI);
+        let report = sample_report();
+        let start = report.prices[0].0;
+        let prices: Vec<_> = (0..96)
+            .map(|i| (start + Duration::minutes(15 * i), 0.5 * i as f32))
+            .collect();
+
+        let table = compose_price_table(&prices, &localization, &report.day_title);
+        assert_eq!(table.rows.len(), 96);
+        assert_eq!(table.rows[0], "00: 0,00");
+        assert_eq!(table.rows[1], "00:15: 0,50");
+        assert_eq!(table.rows[95], "23:45: 47,50");
+    }
 }
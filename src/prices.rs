@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::localization::Localization;
+use crate::statistics::average;
+use crate::{entsoe, history, parser, vat};
+
+/// a price and the start of its slot
+pub type Price = (DateTime<Utc>, f32);
+
+/// time between consecutive prices, one hour if it can't be deduced
+pub fn get_resolution(prices: &[Price]) -> Duration {
+    match prices {
+        [(a, _), (b, _), ..] => *b - *a,
+        _ => Duration::hours(1),
+    }
+}
+
+/// start and end of the local day in UTC, end exclusive
+pub fn get_day_bounds(day: NaiveDate, timezone: &Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start_of_day = |day: NaiveDate| {
+        timezone
+            .from_local_datetime(&day.and_time(NaiveTime::MIN))
+            .unwrap()
+            .with_timezone(&Utc)
+    };
+    (start_of_day(day), start_of_day(day + Duration::days(1)))
+}
+
+/// largest difference between two price series in €/MWh, infinite if the slots differ
+pub fn get_max_difference(a: &[Price], b: &[Price]) -> f32 {
+    if a.len() != b.len() || a.iter().zip(b).any(|((ts_a, _), (ts_b, _))| ts_a != ts_b) {
+        return f32::INFINITY;
+    }
+    a.iter()
+        .zip(b)
+        .map(|((_, price_a), (_, price_b))| (price_a - price_b).abs())
+        .fold(0.0, f32::max)
+}
+
+/// prices for the day in €/MWh, as published by ENTSO-E
+pub async fn fetch_day_prices(
+    apikey: &str,
+    eic: &str,
+    day: NaiveDate,
+    timezone: &Tz,
+) -> Result<Vec<Price>, reqwest::Error> {
+    let prices_xml = entsoe::get_spot_prices(apikey, eic, day, timezone).await?;
+
+    Ok(parser::parse_xml(prices_xml)
+        .into_iter()
+        .filter(|(ts, _)| ts.with_timezone(timezone).date_naive() == day)
+        .collect())
+}
+
+/// convert €/MWh to `<local currency>/kWh`, apply VAT of the delivery day
+pub fn convert_prices(prices: &[Price], localization: &Localization, vat: &vat::Vat) -> Vec<Price> {
+    prices
+        .iter()
+        .map(|(ts, price)| {
+            (
+                *ts,
+                vat.apply(
+                    ts.with_timezone(&localization.timezone).date_naive(),
+                    price * localization.currency_multiplier / 1000.0,
+                ),
+            )
+        })
+        .collect()
+}
+
+/// the series of the day's prices with the conversion of the localization
+pub fn get_series_record(
+    area: &str,
+    day: NaiveDate,
+    prices: &[Price],
+    localization: &Localization,
+    vat: &vat::Vat,
+) -> history::SeriesRecord {
+    history::SeriesRecord {
+        area: area.to_string(),
+        day,
+        resolution_minutes: get_resolution(prices).num_minutes(),
+        vat: vat.rate(day),
+        currency_name: localization.currency_name.to_string(),
+        exchange_rate: localization.currency_multiplier,
+        fetched_at: Utc::now(),
+    }
+}
+
+/// stored prices of the day in €/MWh, at the finest resolution stored for it
+pub fn get_stored_prices(
+    history: &history::History,
+    area: &str,
+    day: NaiveDate,
+    timezone: &Tz,
+) -> Result<Vec<Price>, rusqlite::Error> {
+    let resolution = history
+        .list_series(area, day, day)?
+        .iter()
+        .map(|series| series.resolution_minutes)
+        .min();
+    match resolution {
+        Some(resolution) => {
+            let (start, end) = get_day_bounds(day, timezone);
+            history.get_prices(area, resolution, start, end)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Average of the prices in the history between the days, end exclusive.
+/// Each day is converted with the exchange rate and VAT stored for it, so
+/// that the average matches what was posted.
+pub fn get_history_average(
+    history: &history::History,
+    area: &str,
+    resolution: Duration,
+    (start, end): (NaiveDate, NaiveDate),
+    localization: &Localization,
+    negative_prices: vat::NegativePricePolicy,
+) -> Result<Option<f32>, rusqlite::Error> {
+    let mut prices = Vec::new();
+    for series in history.list_series(area, start, end - Duration::days(1))? {
+        if series.resolution_minutes != resolution.num_minutes() {
+            continue;
+        }
+        let (day_start, day_end) = get_day_bounds(series.day, &localization.timezone);
+        let vat = vat::Vat::flat(series.vat, negative_prices);
+        prices.extend(
+            history
+                .get_prices(area, series.resolution_minutes, day_start, day_end)?
+                .into_iter()
+                .map(|(ts, price)| {
+                    (
+                        ts,
+                        vat.apply(series.day, price * series.exchange_rate / 1000.0),
+                    )
+                }),
+        );
+    }
+
+    Ok((!prices.is_empty()).then(|| average(&prices)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::tests::localization;
+
+    #[test]
+    fn history_average_uses_the_stored_conversion() {
+        let mut history = history::History::open(":memory:").unwrap();
+        let localization = localization(chrono::Locale::fi_FI);
+        let area = "10YFI-1--------U";
+        let first = NaiveDate::from_ymd_opt(2024, 8, 31).unwrap();
+
+        // 24 % VAT before and 25.5 % after the change, both at 100 €/MWh
+        for (day, vat) in [(first, 24.0), (first + Duration::days(1), 25.5)] {
+            let start = get_day_bounds(day, &localization.timezone).0;
+            let prices: Vec<_> = (0..24)
+                .map(|i| (start + Duration::hours(i), 100.0))
+                .collect();
+            let series = history::SeriesRecord {
+                vat,
+                ..get_series_record(
+                    area,
+                    day,
+                    &prices,
+                    &localization,
+                    &vat::Vat::flat(vat, vat::NegativePricePolicy::Exempt),
+                )
+            };
+            history.save_series(&series, &prices).unwrap();
+        }
+
+        let average = |days| {
+            get_history_average(
+                &history,
+                area,
+                Duration::hours(1),
+                days,
+                &localization,
+                vat::NegativePricePolicy::Exempt,
+            )
+            .unwrap()
+        };
+        let close_to = |average: Option<f32>, expected: f32| {
+            average.is_some_and(|average| (average - expected).abs() < 1e-4)
+        };
+        assert!(close_to(average((first, first + Duration::days(1))), 12.4));
+        assert!(close_to(
+            average((first, first + Duration::days(2))),
+            12.475
+        ));
+        assert_eq!(average((first - Duration::days(7), first)), None);
+    }
+}
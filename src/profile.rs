@@ -0,0 +1,85 @@
+use bsky_sdk::api::{
+    agent::Session,
+    com::atproto::repo::{get_record, put_record},
+    xrpc::error::{Error, XrpcErrorKind},
+};
+use serde_json::{json, Value};
+
+use crate::compressor;
+use crate::session::Agent;
+
+static PROFILE_COLLECTION: &str = "app.bsky.actor.profile";
+
+// Bluesky's limit for the profile description in graphemes
+pub static MAX_DESCRIPTION_LENGTH: usize = 256;
+
+// Replaces the description, and the banner if given, in the account's profile
+// record. Other fields such as the display name and avatar are kept as they
+// are, and the write fails if the profile was changed in between.
+pub async fn update_profile(
+    agent: &Agent,
+    session: &Session,
+    description: String,
+    banner: Option<Vec<u8>>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let existing = match agent
+        .api
+        .com
+        .atproto
+        .repo
+        .get_record(
+            get_record::ParametersData {
+                cid: None,
+                collection: PROFILE_COLLECTION.parse()?,
+                repo: session.data.did.clone().into(),
+                rkey: "self".to_string(),
+            }
+            .into(),
+        )
+        .await
+    {
+        Ok(output) => Some(output.data),
+        Err(Error::XrpcResponse(response))
+            if matches!(
+                response.error,
+                Some(XrpcErrorKind::Custom(get_record::Error::RecordNotFound(_)))
+            ) =>
+        {
+            None
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // the record as JSON keeps also fields this version doesn't know about
+    let (mut record, swap_record) = match existing {
+        Some(existing) => (serde_json::to_value(&existing.value)?, existing.cid),
+        None => (json!({ "$type": PROFILE_COLLECTION }), None),
+    };
+    record["description"] = Value::String(description);
+
+    if let Some(banner) = banner {
+        let image = compressor::compress(banner, compressor::MAX_IMAGE_BYTES)?;
+        let output = agent.api.com.atproto.repo.upload_blob(image.bytes).await?;
+        record["banner"] = serde_json::to_value(&output.data.blob)?;
+    }
+
+    let output = agent
+        .api
+        .com
+        .atproto
+        .repo
+        .put_record(
+            put_record::InputData {
+                collection: PROFILE_COLLECTION.parse()?,
+                record: serde_json::from_value(record)?,
+                repo: session.data.did.clone().into(),
+                rkey: "self".to_string(),
+                swap_commit: None,
+                swap_record,
+                validate: None,
+            }
+            .into(),
+        )
+        .await?;
+    Ok(output.data.uri)
+}
@@ -0,0 +1,383 @@
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[cfg(feature = "bluesky")]
+use crate::bluesky;
+use crate::config::Config;
+use crate::poster::{AltText, PriceTable};
+use crate::{mastodon, matrix, mqtt, telegram, webhook};
+use crate::{Aggregates, CheapestSlots};
+
+// Daily report rendered independently of the output channel. Publishers
+// format it within their own limits.
+#[derive(Clone, Debug)]
+pub struct DailyReport {
+    pub area: String,
+    pub day: NaiveDate,
+    pub day_title: String,
+    // converted prices and their unit, e.g. "c/kWh"
+    pub prices: Vec<(DateTime<Utc>, f32)>,
+    pub unit: String,
+    pub aggregates: Aggregates,
+    // prices as published by ENTSO-E in €/MWh and the VAT rate of the day
+    pub raw_prices: Vec<(DateTime<Utc>, f32)>,
+    pub vat: f32,
+    // header first, then the rest in order of priority
+    pub blocks: Vec<String>,
+    pub link: Option<String>,
+    pub tags: Vec<String>,
+    pub image: Vec<u8>,
+    pub image_filename: String,
+    pub alt_text: AltText,
+    pub price_table: PriceTable,
+    // header replacing the title in corrections
+    pub correction_title: String,
+    // set for re-posts of corrected prices, so that they aren't taken for
+    // retries of the original post
+    pub revision: Option<String>,
+}
+
+impl DailyReport {
+    // the report with the correction title as its header
+    pub fn correction(&self) -> DailyReport {
+        let mut report = self.clone();
+        if let Some(header) = report.blocks.first_mut() {
+            *header = self.correction_title.clone();
+        }
+        report
+    }
+
+    // the report as a re-post, with a revision identifying the prices
+    pub fn revised(&self) -> DailyReport {
+        let mut report = self.clone();
+        report.revision = Some(prices_revision(&self.raw_prices));
+        report
+    }
+
+    // idempotency key of the post, the same for retries of the same revision
+    pub fn post_key(&self) -> String {
+        let marker = post_marker(&self.area, self.day);
+        match &self.revision {
+            Some(revision) => format!("{}:{}", marker, revision),
+            None => marker,
+        }
+    }
+
+    // Blocks separated by empty lines. Lines of the blocks after the header
+    // that don't fit within max_length, as measured by length, are left out,
+    // and a header that doesn't fit alone is cut at a grapheme boundary.
+    pub fn fit_text(&self, max_length: usize, length: impl Fn(&str) -> usize) -> String {
+        let mut text = self.blocks.first().cloned().unwrap_or_default();
+        if length(&text) > max_length {
+            return truncate_text(&text, max_length, length);
+        }
+        for block in self.blocks.iter().skip(1) {
+            let lines: Vec<&str> = block.lines().collect();
+            if let Some(candidate) = (1..=lines.len())
+                .rev()
+                .map(|n| format!("{}\n\n{}", text, lines[..n].join("\n")))
+                .find(|candidate| length(candidate) <= max_length)
+            {
+                text = candidate;
+            }
+        }
+        text
+    }
+}
+
+// short hash identifying the prices of a day
+pub fn prices_revision(raw_prices: &[(DateTime<Utc>, f32)]) -> String {
+    let mut hasher = Sha256::new();
+    for (ts, price) in raw_prices {
+        hasher.update(ts.timestamp().to_be_bytes());
+        hasher.update(price.to_be_bytes());
+    }
+    hasher.finalize()[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn count_graphemes(s: &str) -> usize {
+    s.graphemes(true).count()
+}
+
+// cuts text at a grapheme boundary and appends an ellipsis, if it's too long
+pub fn truncate_text(text: &str, max_length: usize, length: impl Fn(&str) -> usize) -> String {
+    if length(text) <= max_length {
+        return text.to_string();
+    }
+    let mut graphemes: Vec<&str> = text.graphemes(true).collect();
+    while !graphemes.is_empty() && length(&format!("{}…", graphemes.concat())) > max_length {
+        graphemes.pop();
+    }
+    if graphemes.is_empty() {
+        String::new()
+    } else {
+        format!("{}…", graphemes.concat())
+    }
+}
+
+// machine-readable tag identifying the post of an area and delivery day
+pub fn post_marker(area: &str, day: NaiveDate) -> String {
+    format!("spotbot:{}:{}", area, day.format("%Y-%m-%d"))
+}
+
+// price series and aggregates as JSON, for machine-readable outputs
+pub fn price_json((ts, price): &(DateTime<Utc>, f32)) -> Value {
+    json!({ "time": ts.to_rfc3339(), "price": price })
+}
+
+// start and end only if the slots are consecutive, the cheapest slots may
+// be spread over several ranges
+pub fn cheapest_json(cheapest: &Option<CheapestSlots>) -> Value {
+    match cheapest {
+        Some(cheapest) => {
+            let ranges = cheapest.ranges();
+            let (start, end) = match ranges.as_slice() {
+                [(start, end)] => (Some(start.to_rfc3339()), Some(end.to_rfc3339())),
+                _ => (None, None),
+            };
+            json!({
+                "start": start,
+                "end": end,
+                "ranges": ranges
+                    .iter()
+                    .map(|(start, end)| json!({ "start": start.to_rfc3339(), "end": end.to_rfc3339() }))
+                    .collect::<Vec<_>>(),
+                "minutes": cheapest.length.num_minutes(),
+                "avg": cheapest.avg,
+                "slots": cheapest.slots.iter().map(price_json).collect::<Vec<_>>(),
+            })
+        }
+        None => Value::Null,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExistingPost {
+    pub uri: String,
+    // what identified the post, e.g. "marker" or "title"
+    pub matched_by: &'static str,
+}
+
+#[async_trait(?Send)]
+pub trait Publisher {
+    // name of the output channel in the post log
+    fn name(&self) -> &'static str;
+
+    // post already published through this channel for the area and day
+    async fn find_existing(
+        &self,
+        area: &str,
+        day: NaiveDate,
+        day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>>;
+
+    // what would be published, for dry runs
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>>;
+
+    // returns the URI or id of the published post
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>>;
+
+    // whether published posts can be edited when prices are corrected
+    fn supports_update(&self) -> bool {
+        false
+    }
+
+    // edits an earlier post to match the report, returns its URI or id
+    async fn update(
+        &self,
+        _uri: &str,
+        _report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        Err(format!("{} posts can't be edited", self.name()).into())
+    }
+
+    // replies to an earlier post, returns the URI or id of the reply
+    async fn reply(
+        &self,
+        _uri: &str,
+        _report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        Err(format!("{} posts can't be replied to", self.name()).into())
+    }
+
+    async fn delete(&self, _uri: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Err(format!("{} posts can't be deleted", self.name()).into())
+    }
+}
+
+// Publishers listed in SPOTBOT_PUBLISHERS. Without authentication publishers
+// can only preview reports.
+#[cfg_attr(not(feature = "bluesky"), allow(unused_variables))]
+pub async fn get_publishers(
+    config: &Config,
+    authenticate: bool,
+) -> Result<Vec<Box<dyn Publisher>>, Box<dyn std::error::Error>> {
+    let mut publishers: Vec<Box<dyn Publisher>> = Vec::new();
+    for name in &config.publishers {
+        match name.as_str() {
+            #[cfg(feature = "bluesky")]
+            "bluesky" => publishers.push(Box::new(
+                bluesky::BlueskyPublisher::new(config, authenticate).await?,
+            )),
+            #[cfg(not(feature = "bluesky"))]
+            "bluesky" => return Err("The bluesky publisher needs the bluesky feature".into()),
+            "mastodon" => {
+                publishers.push(Box::new(mastodon::MastodonPublisher::new(config).await?))
+            }
+            "matrix" => publishers.push(Box::new(matrix::MatrixPublisher::new(config)?)),
+            "mqtt" => publishers.push(Box::new(mqtt::MqttPublisher::new(config)?)),
+            "telegram" => publishers.push(Box::new(telegram::TelegramPublisher::new(config)?)),
+            "webhook" => publishers.push(Box::new(webhook::WebhookPublisher::new(config)?)),
+            _ => return Err(format!("Unknown publisher: {}", name).into()),
+        }
+    }
+    Ok(publishers)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::localization::tests::localization;
+    use crate::poster;
+    use chrono::{Duration, TimeZone};
+
+    // a Finnish report of hourly prices, for the publishers' tests
+    pub fn sample_report() -> DailyReport {
+        let localization = localization(chrono::Locale::fi_FI);
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 22, 0, 0).unwrap();
+        let raw_prices: Vec<_> = (0..24)
+            .map(|i| (start + Duration::hours(i), 20.0 + i as f32))
+            .collect();
+        let prices: Vec<_> = raw_prices
+            .iter()
+            .map(|(ts, price)| (*ts, price * 0.1))
+            .collect();
+        let aggregates = crate::calculate_aggregates(
+            &prices,
+            &localization.timezone,
+            crate::HourRange { from: 8, to: 20 },
+        );
+        let day_title = crate::get_day_title(&day, &localization);
+
+        DailyReport {
+            area: "10YFI-1--------U".to_string(),
+            day,
+            day_title: day_title.clone(),
+            prices: prices.clone(),
+            unit: "c/kWh".to_string(),
+            aggregates: aggregates.clone(),
+            raw_prices,
+            vat: 25.5,
+            blocks: poster::compose_blocks(
+                &aggregates,
+                &localization,
+                &day_title,
+                &[crate::Statistic::Avg, crate::Statistic::Min],
+                25.5,
+                false,
+            ),
+            link: None,
+            tags: vec!["sähkö".to_string()],
+            // the PNG signature and an IHDR chunk of 2x1 pixels
+            image: b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x01".to_vec(),
+            image_filename: "chart.png".to_string(),
+            alt_text: poster::compose_alt_text(&prices, &aggregates, &localization, &day_title),
+            price_table: poster::compose_price_table(&prices, &localization, &day_title),
+            correction_title: format!("{} {}", localization.post_correction, day_title),
+            revision: None,
+        }
+    }
+
+    #[test]
+    fn revisions_get_their_own_post_keys() {
+        let report = sample_report();
+        assert_eq!(report.post_key(), "spotbot:10YFI-1--------U:2025-01-02");
+
+        let revised = report.revised();
+        assert_ne!(revised.post_key(), report.post_key());
+        assert!(revised.post_key().starts_with(&report.post_key()));
+        // the same prices are the same revision
+        assert_eq!(report.revised().post_key(), revised.post_key());
+
+        let mut corrected = report.clone();
+        corrected.raw_prices[5].1 += 10.0;
+        assert_ne!(corrected.revised().post_key(), revised.post_key());
+    }
+
+    #[test]
+    fn fit_text_counts_graphemes() {
+        let mut report = sample_report();
+        report.blocks = vec![
+            "Pörssisähkö".to_string(),
+            "Hinta ä".repeat(3),
+            "e\u{301}".repeat(5),
+        ];
+
+        // the last block is 5 graphemes but 15 bytes
+        assert_eq!(
+            report.fit_text(42, count_graphemes),
+            format!(
+                "Pörssisähkö\n\n{}\n\n{}",
+                "Hinta ä".repeat(3),
+                "e\u{301}".repeat(5)
+            )
+        );
+        // blocks that don't fit are left out, later ones may still fit
+        assert_eq!(
+            report.fit_text(25, count_graphemes),
+            format!("Pörssisähkö\n\n{}", "e\u{301}".repeat(5))
+        );
+        assert_eq!(
+            report.fit_text(42, str::len),
+            format!("Pörssisähkö\n\n{}", "Hinta ä".repeat(3))
+        );
+    }
+
+    #[test]
+    fn long_headers_are_cut_at_graphemes() {
+        let mut report = sample_report();
+        report.blocks = vec![
+            format!("Sähkö {}", "e\u{301}".repeat(5)),
+            "Hinta".to_string(),
+        ];
+
+        let text = report.fit_text(8, count_graphemes);
+        assert_eq!(text, "Sähkö e\u{301}…");
+        // the cut doesn't split the combining accent off its letter
+        let text = report.fit_text(15, str::len);
+        assert_eq!(text, "Sähkö e\u{301}…");
+        assert_eq!(report.fit_text(0, count_graphemes), "");
+    }
+
+    #[test]
+    fn all_statistics_fit_bluesky_posts() {
+        let localization = localization(chrono::Locale::fi_FI);
+        let mut report = sample_report();
+        let statistics: Vec<crate::Statistic> =
+            "avg,min,max,median,p10,p90,base,peak,offpeak,spread,stddev"
+                .split(',')
+                .map(|name| name.parse().unwrap())
+                .collect();
+        report.blocks = poster::compose_blocks(
+            &report.aggregates,
+            &localization,
+            &report.day_title,
+            &statistics,
+            25.5,
+            true,
+        );
+        let text = report.fit_text(300, count_graphemes);
+        assert!(count_graphemes(&text) <= 300);
+        // the statistics that fit are kept, the rest of the lines left out
+        assert!(count_graphemes(&report.blocks[1]) > 300 - count_graphemes(&report.blocks[0]));
+        assert!(text.contains("Huippuhinta (ma-pe 08-20): "));
+        assert!(!text.contains("Keskihajonta"));
+    }
+}
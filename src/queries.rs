@@ -0,0 +1,214 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::statistics::{average, find_cheapest_window, slots_in_range};
+use crate::{poster, CheapestQuery, HourRange, Localization};
+
+// questions answered by the reply bot
+#[derive(Clone, Copy, Debug)]
+pub enum Query {
+    // cheapest consecutive hours, e.g. "cheapest 3 h" or "cheapest 2 h 18-24"
+    Cheapest(CheapestQuery),
+    // e.g. "price at 18"
+    PriceAt(u32),
+    // e.g. "average 18-20" or just "18-20"
+    Average(HourRange),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueryDay {
+    Today,
+    Tomorrow,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Question {
+    pub query: Query,
+    // None if the question doesn't say
+    pub day: Option<QueryDay>,
+}
+
+// hour of "18", "18:00" or "18.00"
+fn parse_hour(s: &str) -> Option<u32> {
+    let hour = s.split([':', '.']).next()?.parse::<u32>().ok()?;
+    (hour <= 24).then_some(hour % 24)
+}
+
+pub fn parse_question(text: &str, localization: &Localization) -> Option<Question> {
+    let text = text
+        .to_lowercase()
+        .replace(['–', '—'], "-")
+        .replace(" - ", "-");
+    let tokens = text
+        .split_whitespace()
+        // mentions of the bot
+        .filter(|token| !token.starts_with('@'))
+        .map(|token| token.trim_matches(|c: char| !c.is_alphanumeric() && c != ':'))
+        .collect::<Vec<_>>();
+    let has = |words: &[&str]| tokens.iter().any(|token| words.contains(token));
+
+    let day = if has(localization.query_tomorrow) {
+        Some(QueryDay::Tomorrow)
+    } else if has(localization.query_today) {
+        Some(QueryDay::Today)
+    } else {
+        None
+    };
+
+    let range = tokens.iter().find_map(|token| {
+        let (from, to) = token.split_once('-')?;
+        Some(HourRange {
+            from: parse_hour(from)?,
+            to: parse_hour(to)?,
+        })
+    });
+    let is_at = |i: usize| i > 0 && tokens[i - 1] == localization.post_at;
+
+    let query = if has(localization.query_cheapest) {
+        // the first number that isn't a time, one hour by default
+        let hours = tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !is_at(*i))
+            .find_map(|(_, token)| token.trim_end_matches('h').parse::<i64>().ok())
+            .filter(|hours| (1..=24).contains(hours))
+            .unwrap_or(1);
+        Query::Cheapest(CheapestQuery {
+            length: Duration::hours(hours),
+            between: range,
+        })
+    } else if let Some(range) = range {
+        Query::Average(range)
+    } else {
+        let hour = tokens
+            .iter()
+            .enumerate()
+            .find_map(|(i, token)| is_at(i).then(|| parse_hour(token)).flatten())
+            .or_else(|| {
+                tokens
+                    .iter()
+                    .filter(|token| token.contains(':'))
+                    .find_map(|token| parse_hour(token))
+            })?;
+        Query::PriceAt(hour)
+    };
+
+    Some(Question { query, day })
+}
+
+// answer from the day's converted prices, None if there's nothing to tell
+pub fn answer(
+    query: &Query,
+    prices: &[(DateTime<Utc>, f32)],
+    localization: &Localization,
+    day_title: &str,
+) -> Option<String> {
+    let average_in = |range: HourRange| {
+        let slots = prices
+            .iter()
+            .zip(slots_in_range(prices, Some(range), &localization.timezone))
+            .filter(|(_, in_range)| *in_range)
+            .map(|(price, _)| *price)
+            .collect::<Vec<_>>();
+        (!slots.is_empty()).then(|| average(&slots))
+    };
+    let format_price = |range: HourRange, price: f32| {
+        format!(
+            "{} {:02}-{:02}: {} {}/kWh",
+            localization.post_at,
+            range.from,
+            range.to,
+            poster::format_number(price, 2, localization),
+            localization.currency_name
+        )
+    };
+
+    let text = match query {
+        Query::Cheapest(query) => poster::format_cheapest(
+            localization.post_cheapest_window,
+            find_cheapest_window(prices, query, &localization.timezone).as_ref(),
+            localization,
+        )?,
+        Query::PriceAt(hour) => {
+            let range = HourRange {
+                from: *hour,
+                to: (hour + 1) % 24,
+            };
+            format_price(range, average_in(range)?)
+        }
+        Query::Average(range) => format!(
+            "{} {}",
+            localization.post_avg,
+            format_price(*range, average_in(*range)?)
+        ),
+    };
+
+    // e.g. "klo 18-19" starts a line
+    let mut chars = text.chars();
+    let text = chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+
+    Some(format!("{}\n{}", day_title, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::tests::localization;
+
+    fn parse(text: &str) -> Question {
+        parse_question(text, &localization(chrono::Locale::fi_FI)).unwrap()
+    }
+
+    #[test]
+    fn cheapest_hours() {
+        let question = parse("@spotbot.bsky.social halvin 3 h huomenna?");
+        assert_eq!(question.day, Some(QueryDay::Tomorrow));
+        let Query::Cheapest(query) = question.query else {
+            panic!("{:?}", question.query);
+        };
+        assert_eq!(query.length, Duration::hours(3));
+        assert!(query.between.is_none());
+
+        let Query::Cheapest(query) = parse("halvin 2h 22–06").query else {
+            panic!();
+        };
+        assert_eq!(query.length, Duration::hours(2));
+        let between = query.between.unwrap();
+        assert_eq!((between.from, between.to), (22, 6));
+    }
+
+    #[test]
+    fn cheapest_hour_by_default() {
+        // the hour after "klo" is a time, not the length
+        let Query::Cheapest(query) = parse("halvin klo 18 jälkeen").query else {
+            panic!();
+        };
+        assert_eq!(query.length, Duration::hours(1));
+    }
+
+    #[test]
+    fn price_at() {
+        let question = parse("Mikä on hinta klo 18 tänään?");
+        assert_eq!(question.day, Some(QueryDay::Today));
+        assert!(matches!(question.query, Query::PriceAt(18)));
+        assert!(matches!(parse("hinta 7:00").query, Query::PriceAt(7)));
+        assert_eq!(parse("hinta 7:00").day, None);
+    }
+
+    #[test]
+    fn average_of_range() {
+        let Query::Average(range) = parse("keskihinta 18 - 20").query else {
+            panic!();
+        };
+        assert_eq!((range.from, range.to), (18, 20));
+    }
+
+    #[test]
+    fn not_a_question() {
+        let localization = localization(chrono::Locale::fi_FI);
+        assert!(parse_question("Kiitos!", &localization).is_none());
+        assert!(parse_question("hinta klo 25", &localization).is_none());
+    }
+}
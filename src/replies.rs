@@ -0,0 +1,394 @@
+use bsky_sdk::api::{
+    agent::Session,
+    app::bsky::{feed::post::RecordData, notification::list_notifications},
+    com::atproto::repo::strong_ref,
+    types::TryFromUnknown,
+};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::Config;
+use crate::history::{History, ReplyRecord};
+use crate::prices::get_stored_prices;
+use crate::queries::{self, QueryDay};
+use crate::richtext::RichText;
+use crate::session::Agent;
+use crate::{bluesky, convert_prices, get_day_title, Localization};
+
+// pause between replies to stay well within Bluesky's rate limits
+static REPLY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+// upper limit for paging notifications back to the last handled one
+static MAX_NOTIFICATION_PAGES: usize = 20;
+
+// Answers price questions in mentions and replies to the bot's posts from the
+// stored price history. Each notification is handled once, and replies are
+// limited per run and per author.
+pub async fn answer_mentions(
+    agent: &Agent,
+    session: &Session,
+    history: &History,
+    config: &Config,
+    localization: &Localization,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let account = &config.account;
+    let notifications =
+        list_new_notifications(agent, history.last_notification_at(account)?).await?;
+
+    let mut replies = 0;
+    // oldest first, so that questions are answered in order
+    for notification in notifications.iter().rev() {
+        if !["mention", "reply"].contains(&notification.reason.as_str())
+            || notification.author.did == session.data.did
+            || history.has_reply(account, &notification.uri)?
+        {
+            continue;
+        }
+        let Ok(record) = RecordData::try_from_unknown(notification.record.clone()) else {
+            continue;
+        };
+
+        let author = notification.author.did.to_string();
+        let mut handled = ReplyRecord {
+            account: account.clone(),
+            notification_uri: notification.uri.clone(),
+            author: author.clone(),
+            reply_uri: None,
+            replied_at: Utc::now(),
+            indexed_at: notification.indexed_at.as_ref().with_timezone(&Utc),
+        };
+
+        if replies >= config.reply_max_per_run {
+            log::info!("Reply limit of the run reached");
+            break;
+        }
+        if author_limit_reached(history, config, &author, Utc::now())? {
+            log::info!("Reply limit reached for {}, skipping", author);
+            history.save_reply(&handled)?;
+            continue;
+        }
+
+        let text = match queries::parse_question(&record.text, localization) {
+            Some(question) => answer(question, history, config, localization)?,
+            // replies to the bot's posts that aren't questions are left alone
+            None if notification.reason == "mention" => localization.reply_help.to_string(),
+            None => {
+                history.save_reply(&handled)?;
+                continue;
+            }
+        };
+
+        let parent: strong_ref::Main = strong_ref::MainData {
+            cid: notification.cid.clone(),
+            uri: notification.uri.clone(),
+        }
+        .into();
+        let root = match &record.reply {
+            Some(reply) => reply.root.clone(),
+            None => parent.clone(),
+        };
+
+        let reply = bluesky::reply(agent, RichText::new().text(&text), root, parent).await?;
+        handled.reply_uri = Some(reply.data.uri);
+        handled.replied_at = Utc::now();
+        history.save_reply(&handled)?;
+        replies += 1;
+
+        tokio::time::sleep(REPLY_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+// Notifications newest first, paged back to the last handled one so that
+// mentions aren't missed among likes and follows. Without handled
+// notifications only the first page is read.
+async fn list_new_notifications(
+    agent: &Agent,
+    last_handled: Option<DateTime<Utc>>,
+) -> Result<Vec<list_notifications::Notification>, Box<dyn std::error::Error>> {
+    let mut notifications = Vec::new();
+    let mut cursor = None;
+    for _ in 0..MAX_NOTIFICATION_PAGES {
+        let page = agent
+            .api
+            .app
+            .bsky
+            .notification
+            .list_notifications(
+                list_notifications::ParametersData {
+                    cursor: cursor.clone(),
+                    limit: Some(50.try_into().unwrap()),
+                    priority: None,
+                    seen_at: None,
+                }
+                .into(),
+            )
+            .await?
+            .data;
+
+        let reached = last_handled.is_none_or(|last| {
+            page.notifications
+                .iter()
+                .any(|notification| *notification.indexed_at.as_ref() <= last)
+        });
+        let empty = page.notifications.is_empty();
+        notifications.extend(page.notifications);
+        match page.cursor {
+            Some(next) if !reached && !empty => cursor = Some(next),
+            _ => return Ok(notifications),
+        }
+    }
+    log::warn!(
+        "Read {} pages of notifications without reaching the last handled one",
+        MAX_NOTIFICATION_PAGES
+    );
+    Ok(notifications)
+}
+
+// whether the author has had the most replies allowed within the last hour
+fn author_limit_reached(
+    history: &History,
+    config: &Config,
+    author: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, rusqlite::Error> {
+    Ok(
+        history.count_replies(&config.account, author, now - Duration::hours(1))?
+            >= config.reply_max_per_author,
+    )
+}
+
+// answer text for the question, tomorrow by default if its prices are known
+fn answer(
+    question: queries::Question,
+    history: &History,
+    config: &Config,
+    localization: &Localization,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let today = Utc::now()
+        .with_timezone(&localization.timezone)
+        .date_naive();
+    let tomorrow = today + Duration::days(1);
+
+    let get_prices = |day| {
+        get_stored_prices(history, &config.entsoe_eic, day, &localization.timezone)
+            .map(|prices| convert_prices(&prices, localization, &config.vat))
+    };
+
+    let (day, prices) = match question.day {
+        Some(QueryDay::Today) => (today, get_prices(today)?),
+        Some(QueryDay::Tomorrow) => (tomorrow, get_prices(tomorrow)?),
+        None => match get_prices(tomorrow)? {
+            prices if !prices.is_empty() => (tomorrow, prices),
+            _ => (today, get_prices(today)?),
+        },
+    };
+    if prices.is_empty() {
+        return Ok(localization.reply_no_prices.to_string());
+    }
+
+    Ok(queries::answer(
+        &question.query,
+        &prices,
+        localization,
+        &get_day_title(&day, localization),
+    )
+    .unwrap_or(localization.reply_help.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SeriesRecord;
+    use crate::localization::tests::localization;
+    use crate::queries::{Query, Question};
+    use crate::{get_day_bounds, get_resolution};
+    use chrono::TimeZone;
+
+    fn config() -> Config {
+        crate::config::tests::config(&[
+            ("BLUESKY_USERNAME", "spotbot.bsky.social"),
+            ("REPLY_MAX_PER_AUTHOR", "2"),
+        ])
+    }
+
+    fn reply(author: &str, i: usize, replied_at: DateTime<Utc>, sent: bool) -> ReplyRecord {
+        ReplyRecord {
+            account: "spotbot.bsky.social".to_string(),
+            notification_uri: format!("at://{}/app.bsky.feed.post/{}", author, i),
+            author: author.to_string(),
+            reply_uri: sent.then(|| format!("at://spotbot/app.bsky.feed.post/{}", i)),
+            replied_at,
+            indexed_at: replied_at,
+        }
+    }
+
+    // a notification indexed at the time, in the JSON of the XRPC API
+    fn notification(reason: &str, indexed_at: &str) -> serde_json::Value {
+        serde_json::json!({
+            "uri": format!("at://did:plc:a/app.bsky.feed.post/{}", indexed_at),
+            "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm",
+            "author": { "did": "did:plc:a", "handle": "a.bsky.social" },
+            "reason": reason,
+            "record": {
+                "$type": "app.bsky.feed.post",
+                "text": "@spotbot.bsky.social halvin tunti?",
+                "createdAt": indexed_at,
+            },
+            "isRead": false,
+            "indexedAt": indexed_at,
+        })
+    }
+
+    #[tokio::test]
+    async fn notifications_are_paged_to_the_last_handled() {
+        use mockito::Matcher;
+
+        let mut server = mockito::Server::new_async().await;
+        let agent = crate::session::tests::agent(server.url()).await;
+        let path = "/xrpc/app.bsky.notification.listNotifications";
+        let page = |cursor: &str| {
+            Matcher::AllOf(vec![
+                Matcher::UrlEncoded("limit".to_string(), "50".to_string()),
+                Matcher::UrlEncoded("cursor".to_string(), cursor.to_string()),
+            ])
+        };
+
+        let first = server
+            .mock("GET", path)
+            .match_query(Matcher::Regex("^limit=50$".to_string()))
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "cursor": "2",
+                    "notifications": [
+                        notification("like", "2025-01-02T12:30:00.000Z"),
+                        notification("follow", "2025-01-02T12:20:00.000Z"),
+                    ],
+                })
+                .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let second = server
+            .mock("GET", path)
+            .match_query(page("2"))
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "cursor": "3",
+                    "notifications": [
+                        notification("mention", "2025-01-02T12:10:00.000Z"),
+                        notification("mention", "2025-01-02T10:00:00.000Z"),
+                    ],
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let third = server
+            .mock("GET", path)
+            .match_query(page("3"))
+            .expect(0)
+            .create_async()
+            .await;
+
+        // only the first page without handled notifications
+        let notifications = list_new_notifications(&agent, None).await.unwrap();
+        assert_eq!(notifications.len(), 2);
+
+        // the mention after likes and follows is found
+        let last_handled = Utc.with_ymd_and_hms(2025, 1, 2, 11, 0, 0).unwrap();
+        let notifications = list_new_notifications(&agent, Some(last_handled))
+            .await
+            .unwrap();
+        assert_eq!(notifications.len(), 4);
+        assert_eq!(notifications[2].reason, "mention");
+
+        first.assert_async().await;
+        second.assert_async().await;
+        third.assert_async().await;
+    }
+
+    #[test]
+    fn last_handled_notification() {
+        let history = History::open(":memory:").unwrap();
+        assert!(history
+            .last_notification_at("spotbot.bsky.social")
+            .unwrap()
+            .is_none());
+
+        let now = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        history
+            .save_reply(&reply("did:plc:a", 1, now, false))
+            .unwrap();
+        history
+            .save_reply(&reply("did:plc:b", 2, now - Duration::hours(1), true))
+            .unwrap();
+        assert_eq!(
+            history.last_notification_at("spotbot.bsky.social").unwrap(),
+            Some(now)
+        );
+    }
+
+    #[test]
+    fn replies_are_limited_per_author() {
+        let history = History::open(":memory:").unwrap();
+        let config = config();
+        let now = Utc::now();
+
+        history
+            .save_reply(&reply("did:plc:a", 1, now - Duration::minutes(90), true))
+            .unwrap();
+        history
+            .save_reply(&reply("did:plc:a", 2, now - Duration::minutes(30), true))
+            .unwrap();
+        // skipped notifications don't count
+        history
+            .save_reply(&reply("did:plc:a", 3, now - Duration::minutes(20), false))
+            .unwrap();
+        assert!(!author_limit_reached(&history, &config, "did:plc:a", now).unwrap());
+
+        history
+            .save_reply(&reply("did:plc:a", 4, now - Duration::minutes(10), true))
+            .unwrap();
+        assert!(author_limit_reached(&history, &config, "did:plc:a", now).unwrap());
+        assert!(!author_limit_reached(&history, &config, "did:plc:b", now).unwrap());
+    }
+
+    #[test]
+    fn answers_from_quarter_hourly_prices() {
+        let mut history = History::open(":memory:").unwrap();
+        let config = config();
+        let localization = localization(chrono::Locale::fi_FI);
+        let today = Utc::now()
+            .with_timezone(&localization.timezone)
+            .date_naive();
+
+        let (start, end) = get_day_bounds(today, &localization.timezone);
+        let prices: Vec<_> = (0..)
+            .map(|i| start + Duration::minutes(15 * i))
+            .take_while(|ts| *ts < end)
+            .map(|ts| (ts, 100.0))
+            .collect();
+        let series = SeriesRecord {
+            area: config.entsoe_eic.clone(),
+            day: today,
+            resolution_minutes: get_resolution(&prices).num_minutes(),
+            vat: 25.5,
+            currency_name: "c".to_string(),
+            exchange_rate: 100.0,
+            fetched_at: Utc::now(),
+        };
+        history.save_series(&series, &prices).unwrap();
+
+        let question = Question {
+            query: Query::PriceAt(18),
+            day: Some(QueryDay::Today),
+        };
+        let text = answer(question, &history, &config, &localization).unwrap();
+        assert!(text.ends_with("Klo 18-19: 12,55 c/kWh"), "{}", text);
+    }
+}
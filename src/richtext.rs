@@ -0,0 +1,129 @@
+use bsky_sdk::api::{
+    app::bsky::richtext::facet::{
+        ByteSliceData, LinkData, Main, MainData, MainFeaturesItem, MentionData, TagData,
+    },
+    types::{string::Did, Union},
+};
+
+// Post text with facets for hashtags, links and mentions. Facet indices are
+// UTF-8 byte offsets, so the text is composed here to keep them in sync.
+#[derive(Clone, Debug, Default)]
+pub struct RichText {
+    pub text: String,
+    pub facets: Vec<Main>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.text.push_str(text);
+        self
+    }
+
+    fn feature(mut self, text: &str, feature: MainFeaturesItem) -> Self {
+        let byte_start = self.text.len();
+        self.text.push_str(text);
+        let byte_end = self.text.len();
+
+        self.facets.push(
+            MainData {
+                features: vec![Union::Refs(feature)],
+                index: ByteSliceData {
+                    byte_end,
+                    byte_start,
+                }
+                .into(),
+            }
+            .into(),
+        );
+        self
+    }
+
+    // tag without the leading #
+    pub fn tag(self, tag: &str) -> Self {
+        let feature = MainFeaturesItem::Tag(Box::new(
+            TagData {
+                tag: tag.to_string(),
+            }
+            .into(),
+        ));
+        self.feature(&format!("#{}", tag), feature)
+    }
+
+    pub fn link(self, text: &str, uri: &str) -> Self {
+        let feature = MainFeaturesItem::Link(Box::new(
+            LinkData {
+                uri: uri.to_string(),
+            }
+            .into(),
+        ));
+        self.feature(text, feature)
+    }
+
+    // handle without the leading @
+    pub fn mention(self, handle: &str, did: Did) -> Self {
+        let feature = MainFeaturesItem::Mention(Box::new(MentionData { did }.into()));
+        self.feature(&format!("@{}", handle), feature)
+    }
+
+    // items separated by separator, e.g. tags separated by spaces
+    pub fn join<T>(mut self, items: &[T], separator: &str, add: impl Fn(Self, &T) -> Self) -> Self {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self = self.text(separator);
+            }
+            self = add(self, item);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the facet's byte range as a slice of the text
+    fn facet_text(rich_text: &RichText, i: usize) -> &str {
+        let index = &rich_text.facets[i].index;
+        &rich_text.text[index.byte_start..index.byte_end]
+    }
+
+    #[test]
+    fn facets_after_multibyte_text() {
+        let rich_text = RichText::new()
+            .text("Pörssisähkö huomenna: ")
+            .link("hinnat.fi/ä", "https://hinnat.fi/ä")
+            .text(" Hälytys! ")
+            .tag("sähkö")
+            .text(" å ")
+            .mention(
+                "spotbot.bsky.social",
+                Did::new("did:plc:abc".to_string()).unwrap(),
+            );
+
+        for (i, expected) in ["hinnat.fi/ä", "#sähkö", "@spotbot.bsky.social"]
+            .iter()
+            .enumerate()
+        {
+            let start = rich_text.text.find(expected).unwrap();
+            let index = &rich_text.facets[i].index;
+            assert_eq!(index.byte_start, start);
+            assert_eq!(index.byte_end, start + expected.len());
+            assert_eq!(facet_text(&rich_text, i), *expected);
+        }
+    }
+
+    #[test]
+    fn joined_tags() {
+        let tags = ["sähkö", "pörssi"];
+        let rich_text = RichText::new()
+            .text("ä ")
+            .join(&tags, " ", |rich_text, tag| rich_text.tag(tag));
+        assert_eq!(rich_text.text, "ä #sähkö #pörssi");
+        assert_eq!(facet_text(&rich_text, 0), "#sähkö");
+        assert_eq!(facet_text(&rich_text, 1), "#pörssi");
+    }
+}
@@ -0,0 +1,349 @@
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use bsky_sdk::agent::config::Config;
+use bsky_sdk::api::agent::store::{MemorySessionStore, SessionStore};
+use bsky_sdk::api::agent::Session;
+use bsky_sdk::api::com::atproto::server::create_session;
+use bsky_sdk::api::xrpc::error::{Error, XrpcErrorKind};
+use bsky_sdk::BskyAgent;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+pub type Agent = BskyAgent<ReqwestClient, FileSessionStore>;
+
+fn load_config(session_file: &str) -> Option<Config> {
+    let json = std::fs::read_to_string(session_file).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            log::warn!("Ignoring invalid session file {}: {}", session_file, e);
+            None
+        }
+    }
+}
+
+// Writes the session (access and refresh JWT) readable only by the owner
+fn write_config(session_file: &str, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string_pretty(config)?;
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(session_file)?;
+    // the mode above only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+pub async fn save_session(
+    agent: &Agent,
+    session_file: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_config(session_file, &agent.to_config().await)
+}
+
+// Keeps the session in memory like the default store, and also writes it to
+// the session file whenever the agent changes it, e.g. when the tokens are
+// refreshed in the middle of a daemon run. Without a file it is memory only.
+#[derive(Clone, Default)]
+pub struct FileSessionStore {
+    session_file: Option<String>,
+    memory: MemorySessionStore,
+}
+
+impl FileSessionStore {
+    pub fn new(session_file: &str) -> Self {
+        FileSessionStore {
+            session_file: Some(session_file.to_string()),
+            memory: MemorySessionStore::default(),
+        }
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    async fn get_session(&self) -> Option<Session> {
+        self.memory.get_session().await
+    }
+
+    async fn set_session(&self, session: Session) {
+        self.memory.set_session(session.clone()).await;
+        if let Some(session_file) = &self.session_file {
+            // the endpoint isn't known here, keep the saved one
+            let config = Config {
+                session: Some(session),
+                ..load_config(session_file).unwrap_or_default()
+            };
+            if let Err(e) = write_config(session_file, &config) {
+                log::warn!("Could not save session to {}: {}", session_file, e);
+            }
+        }
+    }
+
+    async fn clear_session(&self) {
+        self.memory.clear_session().await;
+    }
+}
+
+// Agent for public reads without a session, e.g. in dry runs
+pub async fn anonymous_agent() -> Result<Agent, Box<dyn std::error::Error>> {
+    Ok(BskyAgent::builder()
+        .store(FileSessionStore::default())
+        .build()
+        .await?)
+}
+
+fn describe_login_error(username: &str, e: Error<create_session::Error>) -> String {
+    match e {
+        Error::XrpcResponse(response) => match response.error {
+            Some(XrpcErrorKind::Custom(create_session::Error::AuthFactorTokenRequired(_))) => {
+                format!(
+                    "Bluesky login for {} requires a two-factor token, use an app password",
+                    username
+                )
+            }
+            Some(XrpcErrorKind::Custom(create_session::Error::AccountTakedown(_))) => {
+                format!("Bluesky account {} has been taken down", username)
+            }
+            _ if response.status.as_u16() == 401 => format!(
+                "Bluesky login for {} was rejected, check that BLUESKY_PASSWORD is a valid app password",
+                username
+            ),
+            error => format!(
+                "Bluesky login for {} failed with status {}: {:?}",
+                username, response.status, error
+            ),
+        },
+        e => format!("Bluesky login for {} failed: {}", username, e),
+    }
+}
+
+// Resumes the session saved in session_file, refreshing the tokens if needed,
+// and logs in with the password only if there is no usable session. The agent
+// saves later token refreshes to session_file too.
+pub async fn get_agent(
+    username: &str,
+    password: &str,
+    session_file: &str,
+) -> Result<(Agent, Session), Box<dyn std::error::Error>> {
+    let config = load_config(session_file);
+    // log in again at the same service
+    let endpoint = config
+        .as_ref()
+        .map(|config| config.endpoint.clone())
+        .unwrap_or_else(|| Config::default().endpoint);
+
+    if let Some(config) = config.filter(|config| config.session.is_some()) {
+        let store = FileSessionStore::new(session_file);
+        match BskyAgent::builder()
+            .store(store)
+            .config(config)
+            .build()
+            .await
+        {
+            Ok(agent) => {
+                if let Some(session) = agent.get_session().await {
+                    log::info!("Resumed session of {}", session.handle.as_str());
+                    save_session(&agent, session_file).await?;
+                    return Ok((agent, session));
+                }
+            }
+            Err(e) => log::warn!("Could not resume session, logging in: {}", e),
+        }
+    }
+
+    let agent = BskyAgent::builder()
+        .store(FileSessionStore::new(session_file))
+        .config(Config {
+            endpoint,
+            ..Default::default()
+        })
+        .build()
+        .await?;
+    let session = agent
+        .login(username, password)
+        .await
+        .map_err(|e| describe_login_error(username, e))?;
+    save_session(&agent, session_file).await?;
+
+    Ok((agent, session))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // agent without a session file talking to a mock server
+    pub async fn agent(endpoint: String) -> Agent {
+        BskyAgent::builder()
+            .store(FileSessionStore::default())
+            .config(Config {
+                endpoint,
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap()
+    }
+
+    fn session_json(access_jwt: &str, refresh_jwt: &str) -> serde_json::Value {
+        json!({
+            "accessJwt": access_jwt,
+            "refreshJwt": refresh_jwt,
+            "handle": "spotbot.test",
+            "did": "did:plc:spotbot",
+        })
+    }
+
+    fn session_file(name: &str, endpoint: &str, saved: Option<serde_json::Value>) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "spotbot-{}-{}.session.json",
+            name,
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        if let Some(session) = saved {
+            let config = json!({ "endpoint": endpoint, "session": session });
+            std::fs::write(&path, config.to_string()).unwrap();
+        }
+        path
+    }
+
+    fn saved_session(session_file: &str) -> Session {
+        load_config(session_file).unwrap().session.unwrap()
+    }
+
+    fn expired_token() -> String {
+        json!({ "error": "ExpiredToken", "message": "Token has expired" }).to_string()
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_are_resumed() {
+        let mut server = mockito::Server::new_async().await;
+        let resume = server
+            .mock("GET", "/xrpc/com.atproto.server.getSession")
+            .match_header("authorization", "Bearer access-1")
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "handle": "spotbot.test", "did": "did:plc:spotbot" }).to_string())
+            .create_async()
+            .await;
+        let login = server
+            .mock("POST", "/xrpc/com.atproto.server.createSession")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let path = session_file(
+            "resumed",
+            &server.url(),
+            Some(session_json("access-1", "refresh-1")),
+        );
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let (_, session) = get_agent("spotbot.test", "password", &path).await.unwrap();
+        assert_eq!(session.handle.as_str(), "spotbot.test");
+        assert_eq!(saved_session(&path).access_jwt, "access-1");
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        resume.assert_async().await;
+        login.assert_async().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refreshed_tokens_are_saved() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/xrpc/com.atproto.server.getSession";
+        let body = json!({ "handle": "spotbot.test", "did": "did:plc:spotbot" }).to_string();
+        server
+            .mock("GET", path)
+            .with_header("content-type", "application/json")
+            .with_body(&body)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", path)
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(expired_token())
+            .expect(1)
+            .create_async()
+            .await;
+        let refresh = server
+            .mock("POST", "/xrpc/com.atproto.server.refreshSession")
+            .match_header("authorization", "Bearer refresh-1")
+            .with_header("content-type", "application/json")
+            .with_body(session_json("access-2", "refresh-2").to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", path)
+            .match_header("authorization", "Bearer access-2")
+            .with_header("content-type", "application/json")
+            .with_body(&body)
+            .create_async()
+            .await;
+
+        let file = session_file(
+            "refreshed",
+            &server.url(),
+            Some(session_json("access-1", "refresh-1")),
+        );
+        let (agent, _) = get_agent("spotbot.test", "password", &file).await.unwrap();
+        // any later call may find the access token expired
+        agent.api.com.atproto.server.get_session().await.unwrap();
+
+        refresh.assert_async().await;
+        let saved = saved_session(&file);
+        assert_eq!(saved.access_jwt, "access-2");
+        assert_eq!(saved.refresh_jwt, "refresh-2");
+        assert_eq!(load_config(&file).unwrap().endpoint, server.url());
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_fall_back_to_login() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/xrpc/com.atproto.server.getSession")
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(expired_token())
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/xrpc/com.atproto.server.refreshSession")
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(expired_token())
+            .create_async()
+            .await;
+        let login = server
+            .mock("POST", "/xrpc/com.atproto.server.createSession")
+            .with_header("content-type", "application/json")
+            .with_body(session_json("access-2", "refresh-2").to_string())
+            .create_async()
+            .await;
+
+        let path = session_file(
+            "expired",
+            &server.url(),
+            Some(session_json("access-1", "refresh-1")),
+        );
+        let (_, session) = get_agent("spotbot.test", "password", &path).await.unwrap();
+        assert_eq!(session.access_jwt, "access-2");
+        assert_eq!(saved_session(&path).refresh_jwt, "refresh-2");
+        login.assert_async().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
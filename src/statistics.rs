@@ -0,0 +1,561 @@
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::localization::Localization;
+use crate::prices::{get_resolution, Price};
+
+/// statistics of a day's prices, in the unit of the prices
+#[derive(Clone, Debug)]
+pub struct Aggregates {
+    pub min: Price,
+    pub max: Price,
+    pub avg: f32,
+    pub median: f32,
+    pub p10: f32,
+    pub p90: f32,
+    /// baseload price, the average of all hours, shown next to the peak price
+    pub base: f32,
+    /// weekdays within peak_hours local time, None on weekends
+    pub peak: Option<f32>,
+    pub off_peak: Option<f32>,
+    pub peak_hours: HourRange,
+    pub spread: f32,
+    pub std_dev: f32,
+    /// reference averages from the price history for comparisons
+    pub today_avg: Option<f32>,
+    pub last_week_avg: Option<f32>,
+    pub rolling_30d_avg: Option<f32>,
+    pub cheapest_window: Option<CheapestSlots>,
+    pub cheapest_slots: Option<CheapestSlots>,
+}
+
+/// statistics that can be included in the post, configured with POST_STATISTICS
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Statistic {
+    Avg,
+    Min,
+    Max,
+    Median,
+    P10,
+    P90,
+    Base,
+    Peak,
+    OffPeak,
+    Spread,
+    StdDev,
+    VsToday,
+    VsLastWeek,
+    Vs30Days,
+}
+
+impl FromStr for Statistic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "avg" => Ok(Statistic::Avg),
+            "min" => Ok(Statistic::Min),
+            "max" => Ok(Statistic::Max),
+            "median" => Ok(Statistic::Median),
+            "p10" => Ok(Statistic::P10),
+            "p90" => Ok(Statistic::P90),
+            "base" => Ok(Statistic::Base),
+            "peak" => Ok(Statistic::Peak),
+            "offpeak" => Ok(Statistic::OffPeak),
+            "spread" => Ok(Statistic::Spread),
+            "stddev" => Ok(Statistic::StdDev),
+            "vs_today" => Ok(Statistic::VsToday),
+            "vs_last_week" => Ok(Statistic::VsLastWeek),
+            "vs_30d" => Ok(Statistic::Vs30Days),
+            _ => Err(format!("Unknown statistic: {}", s)),
+        }
+    }
+}
+
+/// local hours from..to, to is exclusive and the range may wrap around
+/// midnight, from == to is the whole day, e.g. "0-24"
+#[derive(Clone, Copy, Debug)]
+pub struct HourRange {
+    pub from: u32,
+    pub to: u32,
+}
+
+/// parsed from e.g. "8-20" or "22-07"
+impl FromStr for HourRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('-')
+            .ok_or(format!("Time range must be HH-HH: {}", s))?;
+        let parse_hour = |n: &str| match n.trim().parse::<u32>() {
+            Ok(hour) if hour <= 24 => Ok(hour % 24),
+            _ => Err(format!("Invalid hour in {}", s)),
+        };
+        Ok(HourRange {
+            from: parse_hour(from)?,
+            to: parse_hour(to)?,
+        })
+    }
+}
+
+impl HourRange {
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.from == self.to {
+            true
+        } else if self.from < self.to {
+            self.from <= hour && hour < self.to
+        } else {
+            self.from <= hour || hour < self.to
+        }
+    }
+}
+
+/// length of the searched period with an optional time of day constraint,
+/// parsed from e.g. "3h", "45m" or "3h@22-07"
+#[derive(Clone, Copy, Debug)]
+pub struct CheapestQuery {
+    pub length: Duration,
+    pub between: Option<HourRange>,
+}
+
+impl FromStr for CheapestQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (length_str, between_str) = match s.split_once('@') {
+            Some((length, between)) => (length.trim(), Some(between.trim())),
+            None => (s.trim(), None),
+        };
+
+        let parse_num = |n: &str| {
+            n.parse::<u32>()
+                .map_err(|_| format!("Invalid number in {}", s))
+        };
+
+        let length = if let Some(hours) = length_str.strip_suffix('h') {
+            Duration::hours(parse_num(hours)?.into())
+        } else if let Some(minutes) = length_str.strip_suffix('m') {
+            Duration::minutes(parse_num(minutes)?.into())
+        } else {
+            return Err(format!("Length must end with h or m: {}", s));
+        };
+
+        let between = between_str.map(str::parse).transpose()?;
+
+        Ok(CheapestQuery { length, between })
+    }
+}
+
+/// slots found by find_cheapest_window or find_cheapest_slots
+#[derive(Clone, Debug)]
+pub struct CheapestSlots {
+    /// chronological, consecutive in time for a cheapest window
+    pub slots: Vec<Price>,
+    pub avg: f32,
+    /// length of the slots found, the queried length rounded down to whole slots
+    pub length: Duration,
+    pub resolution: Duration,
+}
+
+impl CheapestSlots {
+    /// start and end of each run of consecutive slots
+    pub fn ranges(&self) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        merge_slots(&self.slots, self.resolution)
+    }
+}
+
+/// start and end of each run of consecutive slots of the resolution
+pub fn merge_slots(slots: &[Price], resolution: Duration) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (ts, _) in slots {
+        match ranges.last_mut() {
+            Some((_, end)) if end == ts => *end = *ts + resolution,
+            _ => ranges.push((*ts, *ts + resolution)),
+        }
+    }
+    ranges
+}
+
+// linear interpolation between the closest ranks, sorted must not be empty
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f32;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f32)
+}
+
+fn is_peak(ts: &DateTime<Utc>, timezone: &Tz, peak_hours: HourRange) -> bool {
+    let local = ts.with_timezone(timezone);
+    local.weekday().number_from_monday() <= 5 && peak_hours.contains(local.hour())
+}
+
+/// statistics of a non-empty series, without the history comparisons and
+/// cheapest times, with peak prices on weekdays within peak_hours
+pub fn calculate_aggregates(prices: &[Price], timezone: &Tz, peak_hours: HourRange) -> Aggregates {
+    let min = *prices
+        .iter()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .unwrap();
+    let max = *prices
+        .iter()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .unwrap();
+    let avg = average(prices);
+
+    let mut sorted = prices.iter().map(|(_, a)| *a).collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let (peak, off_peak): (Vec<_>, Vec<_>) = prices
+        .iter()
+        .partition(|(ts, _)| is_peak(ts, timezone, peak_hours));
+    let std_dev =
+        (prices.iter().map(|(_, a)| (a - avg).powi(2)).sum::<f32>() / prices.len() as f32).sqrt();
+
+    Aggregates {
+        min,
+        max,
+        avg,
+        median: percentile(&sorted, 50.0),
+        p10: percentile(&sorted, 10.0),
+        p90: percentile(&sorted, 90.0),
+        base: avg,
+        peak: (!peak.is_empty()).then(|| average(&peak)),
+        off_peak: (!off_peak.is_empty()).then(|| average(&off_peak)),
+        peak_hours,
+        spread: max.1 - min.1,
+        std_dev,
+        today_avg: None,
+        last_week_avg: None,
+        rolling_30d_avg: None,
+        cheapest_window: None,
+        cheapest_slots: None,
+    }
+}
+
+/// whether each slot starts within the hours, all of them if not set
+pub fn slots_in_range(prices: &[Price], between: Option<HourRange>, timezone: &Tz) -> Vec<bool> {
+    prices
+        .iter()
+        .map(|(ts, _)| match between {
+            Some(range) => range.contains(ts.with_timezone(timezone).hour()),
+            None => true,
+        })
+        .collect()
+}
+
+/// average price of a non-empty series
+pub fn average(prices: &[Price]) -> f32 {
+    prices.iter().map(|(_, a)| a).sum::<f32>() / prices.len() as f32
+}
+
+/// cheapest consecutive slots spanning query.length
+pub fn find_cheapest_window(
+    prices: &[Price],
+    query: &CheapestQuery,
+    timezone: &Tz,
+) -> Option<CheapestSlots> {
+    let resolution = get_resolution(prices);
+    let count = (query.length.num_minutes() / resolution.num_minutes()) as usize;
+    if count == 0 {
+        return None;
+    }
+    let in_range = slots_in_range(prices, query.between, timezone);
+
+    prices
+        .windows(count)
+        .enumerate()
+        .filter(|(i, window)| {
+            in_range[*i..*i + count].iter().all(|x| *x)
+                && window.windows(2).all(|w| w[1].0 - w[0].0 == resolution)
+        })
+        .map(|(_, window)| (window, average(window)))
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(window, avg)| CheapestSlots {
+            slots: window.to_vec(),
+            avg,
+            length: resolution * count as i32,
+            resolution,
+        })
+}
+
+/// cheapest slots spanning query.length in total, not necessarily consecutive
+pub fn find_cheapest_slots(
+    prices: &[Price],
+    query: &CheapestQuery,
+    timezone: &Tz,
+) -> Option<CheapestSlots> {
+    let resolution = get_resolution(prices);
+    let count = (query.length.num_minutes() / resolution.num_minutes()) as usize;
+    let in_range = slots_in_range(prices, query.between, timezone);
+
+    let mut candidates = prices
+        .iter()
+        .zip(in_range)
+        .filter(|(_, in_range)| *in_range)
+        .map(|(price, _)| *price)
+        .collect::<Vec<_>>();
+    if count == 0 || candidates.len() < count {
+        return None;
+    }
+
+    candidates.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+    let mut slots = candidates[..count].to_vec();
+    slots.sort_by_key(|(ts, _)| *ts);
+    let avg = average(&slots);
+
+    Some(CheapestSlots {
+        slots,
+        avg,
+        length: resolution * count as i32,
+        resolution,
+    })
+}
+
+/// aggregates with the cheapest times configured for the account
+pub fn analyze(prices: &[Price], config: &Config, localization: &Localization) -> Aggregates {
+    let mut aggregates = calculate_aggregates(prices, &localization.timezone, config.peak_hours);
+    aggregates.cheapest_window = config
+        .cheapest_window
+        .and_then(|query| find_cheapest_window(prices, &query, &localization.timezone));
+    aggregates.cheapest_slots = config
+        .cheapest_slots
+        .and_then(|query| find_cheapest_slots(prices, &query, &localization.timezone));
+    aggregates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // prices from midnight UTC, one per resolution
+    fn prices(resolution_minutes: i64, values: &[f32]) -> Vec<Price> {
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, price)| {
+                (
+                    start + Duration::minutes(resolution_minutes * i as i64),
+                    *price,
+                )
+            })
+            .collect()
+    }
+
+    // hourly prices of a day in Helsinki, the local hour as the price
+    fn local_day(day: u32) -> Vec<Price> {
+        let start = chrono_tz::Europe::Helsinki
+            .with_ymd_and_hms(2025, 1, day, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        (0..24)
+            .map(|i| (start + Duration::hours(i), i as f32))
+            .collect()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn hours(slots: &CheapestSlots) -> Vec<u32> {
+        slots.slots.iter().map(|(ts, _)| ts.hour()).collect()
+    }
+
+    #[test]
+    fn parse_query() {
+        let query: CheapestQuery = "3h".parse().unwrap();
+        assert_eq!(query.length, Duration::hours(3));
+        assert!(query.between.is_none());
+
+        let query: CheapestQuery = "45m@22-07".parse().unwrap();
+        assert_eq!(query.length, Duration::minutes(45));
+        let between = query.between.unwrap();
+        assert_eq!((between.from, between.to), (22, 7));
+
+        let query: CheapestQuery = "2h@20-24".parse().unwrap();
+        assert_eq!(query.between.unwrap().to, 0);
+        assert!(query.between.unwrap().contains(23));
+
+        assert!("3".parse::<CheapestQuery>().is_err());
+        assert!("xh".parse::<CheapestQuery>().is_err());
+        assert!("3h@22".parse::<CheapestQuery>().is_err());
+        assert!("3h@25-07".parse::<CheapestQuery>().is_err());
+    }
+
+    #[test]
+    fn hour_range_wraps_around_midnight() {
+        let night = HourRange { from: 22, to: 7 };
+        assert!(night.contains(22));
+        assert!(night.contains(0));
+        assert!(night.contains(6));
+        assert!(!night.contains(7));
+        assert!(!night.contains(21));
+
+        let day = HourRange { from: 8, to: 20 };
+        assert!(day.contains(8));
+        assert!(!day.contains(20));
+        assert!(!day.contains(3));
+    }
+
+    #[test]
+    fn whole_day_hour_range() {
+        let all: HourRange = "0-24".parse().unwrap();
+        assert!((0..24).all(|hour| all.contains(hour)));
+
+        let prices = prices(60, &[3.0, 1.0, 2.0]);
+        let query = "1h@0-24".parse().unwrap();
+        let window = find_cheapest_window(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&window), [1]);
+
+        let friday = local_day(3);
+        let aggregates = calculate_aggregates(
+            &friday,
+            &chrono_tz::Europe::Helsinki,
+            "0-24".parse().unwrap(),
+        );
+        assert_eq!(aggregates.peak, Some(11.5));
+        assert!(aggregates.off_peak.is_none());
+    }
+
+    #[test]
+    fn aggregates_of_known_series() {
+        let day_hours = HourRange { from: 8, to: 20 };
+        let aggregates = calculate_aggregates(
+            &prices(60, &[4.0, 1.0, 3.0, 2.0]),
+            &chrono_tz::UTC,
+            day_hours,
+        );
+        assert_eq!(aggregates.min.1, 1.0);
+        assert_eq!(aggregates.max.1, 4.0);
+        assert_eq!(aggregates.avg, 2.5);
+        assert_eq!(aggregates.base, 2.5);
+        // the even number of prices has the median between the middle ones
+        assert_eq!(aggregates.median, 2.5);
+        assert_close(aggregates.p10, 1.3);
+        assert_close(aggregates.p90, 3.7);
+        assert_eq!(aggregates.spread, 3.0);
+        assert_close(aggregates.std_dev, 1.25_f32.sqrt());
+        // 00-04 on a Wednesday
+        assert!(aggregates.peak.is_none());
+        assert_eq!(aggregates.off_peak, Some(2.5));
+
+        let aggregates = calculate_aggregates(&prices(60, &[7.0]), &chrono_tz::UTC, day_hours);
+        assert_eq!(
+            (aggregates.median, aggregates.p10, aggregates.p90),
+            (7.0, 7.0, 7.0)
+        );
+        assert_eq!(aggregates.std_dev, 0.0);
+    }
+
+    #[test]
+    fn peak_by_local_hours_and_weekdays() {
+        let helsinki = chrono_tz::Europe::Helsinki;
+        // Friday, the local midnight is still Thursday in UTC
+        let friday = local_day(3);
+        let aggregates = calculate_aggregates(&friday, &helsinki, HourRange { from: 8, to: 20 });
+        assert_eq!(aggregates.peak, Some(13.5));
+        assert_eq!(aggregates.off_peak, Some(9.5));
+        assert_eq!(aggregates.base, 11.5);
+
+        // peak hours over midnight
+        let aggregates = calculate_aggregates(&friday, &helsinki, HourRange { from: 22, to: 6 });
+        assert_eq!(aggregates.peak, Some(7.5));
+        assert_eq!(aggregates.off_peak, Some(13.5));
+
+        let aggregates =
+            calculate_aggregates(&local_day(4), &helsinki, HourRange { from: 8, to: 20 });
+        assert!(aggregates.peak.is_none());
+        assert_eq!(aggregates.off_peak, Some(11.5));
+    }
+
+    #[test]
+    fn cheapest_window_hourly() {
+        let mut values = vec![10.0; 24];
+        values[3] = 1.0;
+        values[4] = 2.0;
+        values[5] = 1.0;
+        values[10] = 0.0;
+        let prices = prices(60, &values);
+
+        let query = "3h".parse().unwrap();
+        let window = find_cheapest_window(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&window), [3, 4, 5]);
+        assert_eq!(window.avg, 4.0 / 3.0);
+        assert_eq!(window.length, Duration::hours(3));
+        assert_eq!(window.resolution, Duration::hours(1));
+
+        let query = "1h@6-24".parse().unwrap();
+        let window = find_cheapest_window(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&window), [10]);
+    }
+
+    #[test]
+    fn lengths_round_down_to_whole_slots() {
+        let prices = prices(60, &[3.0, 1.0, 2.0, 5.0]);
+        let query = "90m".parse().unwrap();
+        let window = find_cheapest_window(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&window), [1]);
+        assert_eq!(window.length, Duration::hours(1));
+
+        let query = "30m".parse().unwrap();
+        assert!(find_cheapest_window(&prices, &query, &chrono_tz::UTC).is_none());
+        assert!(find_cheapest_slots(&prices, &query, &chrono_tz::UTC).is_none());
+    }
+
+    #[test]
+    fn cheapest_window_quarter_hourly() {
+        let mut values = vec![10.0; 96];
+        // 02:30-03:15
+        values[10] = 1.0;
+        values[11] = 1.0;
+        values[12] = 1.0;
+        let prices = prices(15, &values);
+
+        let query = "45m".parse().unwrap();
+        let window = find_cheapest_window(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(window.slots.len(), 3);
+        assert_eq!(window.slots[0].0, prices[10].0);
+        assert_eq!(window.length, Duration::minutes(45));
+        assert_eq!(window.ranges(), [(prices[10].0, prices[13].0)]);
+    }
+
+    #[test]
+    fn cheapest_slots_hourly() {
+        let mut values = vec![10.0; 24];
+        values[2] = 1.0;
+        values[23] = 0.0;
+        values[12] = 2.0;
+        let prices = prices(60, &values);
+
+        let query = "3h".parse().unwrap();
+        let slots = find_cheapest_slots(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&slots), [2, 12, 23]);
+        assert_eq!(slots.avg, 1.0);
+        assert_eq!(slots.ranges().len(), 3);
+
+        let query = "2h@0-12".parse().unwrap();
+        let slots = find_cheapest_slots(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(hours(&slots), [0, 2]);
+    }
+
+    #[test]
+    fn cheapest_slots_quarter_hourly() {
+        let mut values = vec![10.0; 96];
+        values[4] = 1.0;
+        values[5] = 1.0;
+        values[40] = 2.0;
+        let prices = prices(15, &values);
+
+        let query = "45m".parse().unwrap();
+        let slots = find_cheapest_slots(&prices, &query, &chrono_tz::UTC).unwrap();
+        assert_eq!(slots.slots.len(), 3);
+        assert_eq!(slots.length, Duration::minutes(45));
+        assert_eq!(
+            slots.ranges(),
+            [(prices[4].0, prices[6].0), (prices[40].0, prices[41].0)]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::publisher::{DailyReport, ExistingPost, Publisher};
+
+static DEFAULT_API_URL: &str = "https://api.telegram.org";
+static MAX_CAPTION_LENGTH: usize = 1024;
+
+#[derive(Deserialize, Debug)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    ok: bool,
+    result: Option<Message>,
+    description: Option<String>,
+}
+
+pub struct TelegramPublisher {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    chat_id: String,
+}
+
+impl TelegramPublisher {
+    pub fn new(config: &Config) -> Result<TelegramPublisher, Box<dyn std::error::Error>> {
+        Ok(TelegramPublisher {
+            client: reqwest::Client::new(),
+            api_url: config
+                .telegram_url
+                .as_deref()
+                .unwrap_or(DEFAULT_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            token: config
+                .telegram_token
+                .clone()
+                .ok_or("TELEGRAM_TOKEN must be set for the telegram publisher")?,
+            chat_id: config
+                .telegram_chat_id
+                .clone()
+                .ok_or("TELEGRAM_CHAT_ID must be set for the telegram publisher")?,
+        })
+    }
+
+    fn compose(&self, report: &DailyReport) -> String {
+        let mut extras = String::new();
+        if let Some(link) = &report.link {
+            extras.push_str(&format!("\n\n{}", link));
+        }
+        if !report.tags.is_empty() {
+            extras.push_str(if report.link.is_some() { "\n" } else { "\n\n" });
+            extras.push_str(
+                &report
+                    .tags
+                    .iter()
+                    .map(|tag| format!("#{}", tag))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+
+        let text = report.fit_text(
+            MAX_CAPTION_LENGTH.saturating_sub(extras.chars().count()),
+            |s| s.chars().count(),
+        );
+        text + &extras
+    }
+
+    fn photo(report: &DailyReport) -> Result<Part, Box<dyn std::error::Error>> {
+        Ok(Part::bytes(report.image.clone())
+            .file_name(report.image_filename.clone())
+            .mime_str("image/png")?)
+    }
+
+    async fn call(&self, method: &str, form: Form) -> Result<Message, Box<dyn std::error::Error>> {
+        // the URL has the bot token, keep it out of the errors
+        let response: Response = self
+            .client
+            .post(format!("{}/bot{}/{}", self.api_url, self.token, method))
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?
+            .json()
+            .await
+            .map_err(|e| e.without_url())?;
+
+        match response {
+            Response {
+                ok: true,
+                result: Some(message),
+                ..
+            } => Ok(message),
+            Response { description, .. } => Err(format!(
+                "Telegram {} failed: {}",
+                method,
+                description.unwrap_or("unknown error".to_string())
+            )
+            .into()),
+        }
+    }
+}
+
+// posts are stored in the post log as "<chat id>/<message id>"
+fn format_uri(message: &Message) -> String {
+    format!("{}/{}", message.chat.id, message.message_id)
+}
+
+#[async_trait(?Send)]
+impl Publisher for TelegramPublisher {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    // the Bot API can't read the channel history, so existing posts are only
+    // found in the local post log
+    async fn find_existing(
+        &self,
+        _area: &str,
+        _day: NaiveDate,
+        _day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.compose(report))
+    }
+
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let form = Form::new()
+            .text("chat_id", self.chat_id.clone())
+            .text("caption", self.compose(report))
+            .part("photo", Self::photo(report)?);
+
+        Ok(format_uri(&self.call("sendPhoto", form).await?))
+    }
+
+    fn supports_update(&self) -> bool {
+        true
+    }
+
+    // replaces both the chart and the caption of the earlier message
+    async fn update(
+        &self,
+        uri: &str,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let (chat_id, message_id) = uri
+            .rsplit_once('/')
+            .ok_or(format!("Invalid Telegram message: {}", uri))?;
+        let media = json!({
+            "type": "photo",
+            "media": "attach://photo",
+            "caption": self.compose(report),
+        });
+        let form = Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("message_id", message_id.to_string())
+            .text("media", media.to_string())
+            .part("photo", Self::photo(report)?);
+
+        Ok(format_uri(&self.call("editMessageMedia", form).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::publisher::tests::sample_report;
+    use mockito::Matcher;
+
+    static MESSAGE: &str = r#"{"ok": true, "result": {"message_id": 7, "chat": {"id": -100}}}"#;
+
+    fn publisher(server: &mockito::Server) -> TelegramPublisher {
+        TelegramPublisher::new(&config(&[
+            ("TELEGRAM_URL", &server.url()),
+            ("TELEGRAM_TOKEN", "123:abc"),
+            ("TELEGRAM_CHAT_ID", "@spotbot"),
+        ]))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_the_chart_as_a_photo() {
+        let mut server = mockito::Server::new_async().await;
+        let send = server
+            .mock("POST", "/bot123:abc/sendPhoto")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#"name="chat_id"\r\n\r\n@spotbot"#.to_string()),
+                Matcher::Regex(r#"name="caption"\r\n\r\nPörssisähkön"#.to_string()),
+                Matcher::Regex(r#"name="photo"; filename="chart.png""#.to_string()),
+            ]))
+            .with_body(MESSAGE)
+            .create_async()
+            .await;
+
+        let uri = publisher(&server).publish(&sample_report()).await.unwrap();
+        assert_eq!(uri, "-100/7");
+        send.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn corrections_edit_the_message() {
+        let mut server = mockito::Server::new_async().await;
+        let edit = server
+            .mock("POST", "/bot123:abc/editMessageMedia")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#"name="chat_id"\r\n\r\n-100\r\n"#.to_string()),
+                Matcher::Regex(r#"name="message_id"\r\n\r\n7\r\n"#.to_string()),
+                Matcher::Regex(r#""media":"attach://photo""#.to_string()),
+                Matcher::Regex(r#"name="photo"; filename="chart.png""#.to_string()),
+            ]))
+            .with_body(MESSAGE)
+            .create_async()
+            .await;
+
+        let uri = publisher(&server)
+            .update("-100/7", &sample_report())
+            .await
+            .unwrap();
+        assert_eq!(uri, "-100/7");
+        edit.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_returned() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/bot123:abc/sendPhoto")
+            .with_body(r#"{"ok": false, "description": "Bad Request: chat not found"}"#)
+            .create_async()
+            .await;
+
+        let error = publisher(&server)
+            .publish(&sample_report())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Telegram sendPhoto failed: Bad Request: chat not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_leave_out_the_token() {
+        let publisher = TelegramPublisher {
+            client: reqwest::Client::new(),
+            // nothing listens on port 1
+            api_url: "http://127.0.0.1:1".to_string(),
+            token: "123456:secret-token".to_string(),
+            chat_id: "@spotbot".to_string(),
+        };
+        let error = publisher
+            .call("sendMessage", Form::new().text("text", "hello"))
+            .await
+            .unwrap_err();
+        assert!(!error.to_string().contains("secret-token"), "{}", error);
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::NaiveDate;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NegativePricePolicy {
+    // VAT is applied to all prices, negative prices become more negative
+    Apply,
+    // negative prices are shown without VAT, like Finnish retailers do
+    Exempt,
+}
+
+impl NegativePricePolicy {
+    // only Finnish retailers are known to leave negative prices without VAT
+    pub fn default_for(country: &str) -> NegativePricePolicy {
+        match country {
+            "FI" => NegativePricePolicy::Exempt,
+            _ => NegativePricePolicy::Apply,
+        }
+    }
+}
+
+impl std::str::FromStr for NegativePricePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "apply" => Ok(NegativePricePolicy::Apply),
+            "exempt" => Ok(NegativePricePolicy::Exempt),
+            _ => Err(format!("Unknown negative price policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VatRule {
+    // first day the rate is effective, None if since the beginning of time
+    pub from: Option<NaiveDate>,
+    // first day the rate is no longer effective, None if still in effect
+    pub until: Option<NaiveDate>,
+    pub rate: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Vat {
+    pub rules: Vec<VatRule>,
+    pub negative_prices: NegativePricePolicy,
+}
+
+fn date(y: i32, m: u32, d: u32) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(y, m, d)
+}
+
+fn rule(from: Option<NaiveDate>, until: Option<NaiveDate>, rate: f32) -> VatRule {
+    VatRule { from, until, rate }
+}
+
+// VAT rates for electricity by country, ordered by effective date
+fn get_country_rules(country: &str) -> Option<Vec<VatRule>> {
+    match country {
+        "FI" => Some(vec![
+            rule(None, date(2022, 12, 1), 24.0),
+            // temporary reduction for the winter 2022-2023
+            rule(date(2022, 12, 1), date(2023, 5, 1), 10.0),
+            rule(date(2023, 5, 1), date(2024, 9, 1), 24.0),
+            rule(date(2024, 9, 1), None, 25.5),
+        ]),
+        "EE" => Some(vec![
+            rule(None, date(2024, 1, 1), 20.0),
+            rule(date(2024, 1, 1), date(2025, 7, 1), 22.0),
+            rule(date(2025, 7, 1), None, 24.0),
+        ]),
+        "LV" | "LT" => Some(vec![rule(None, None, 21.0)]),
+        "SE" => Some(vec![rule(None, None, 25.0)]),
+        "DK" => Some(vec![rule(None, None, 25.0)]),
+        "NO" => Some(vec![rule(None, None, 25.0)]),
+        _ => None,
+    }
+}
+
+impl Vat {
+    pub fn flat(rate: f32, negative_prices: NegativePricePolicy) -> Vat {
+        Vat {
+            rules: vec![rule(None, None, rate)],
+            negative_prices,
+        }
+    }
+
+    pub fn for_country(country: &str, negative_prices: NegativePricePolicy) -> Option<Vat> {
+        get_country_rules(country).map(|rules| Vat {
+            rules,
+            negative_prices,
+        })
+    }
+
+    // VAT percentage effective on the given delivery day, 0 if no rule matches
+    pub fn rate(&self, day: NaiveDate) -> f32 {
+        self.rules
+            .iter()
+            .find(|r| {
+                r.from.is_none_or(|from| from <= day) && r.until.is_none_or(|until| day < until)
+            })
+            .map(|r| r.rate)
+            .unwrap_or(0.0)
+    }
+
+    pub fn apply(&self, day: NaiveDate, price: f32) -> f32 {
+        if price < 0.0 && self.negative_prices == NegativePricePolicy::Exempt {
+            price
+        } else {
+            price * (self.rate(day) / 100.0 + 1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn rate_changes_on_the_effective_day() {
+        let vat = Vat::for_country("FI", NegativePricePolicy::Exempt).unwrap();
+        assert_eq!(vat.rate(day(2024, 8, 31)), 24.0);
+        assert_eq!(vat.rate(day(2024, 9, 1)), 25.5);
+        assert_eq!(vat.rate(day(2024, 9, 2)), 25.5);
+    }
+
+    #[test]
+    fn temporary_rate_ends_on_the_until_day() {
+        let vat = Vat::for_country("FI", NegativePricePolicy::Exempt).unwrap();
+        assert_eq!(vat.rate(day(2022, 11, 30)), 24.0);
+        assert_eq!(vat.rate(day(2022, 12, 1)), 10.0);
+        assert_eq!(vat.rate(day(2023, 4, 30)), 10.0);
+        assert_eq!(vat.rate(day(2023, 5, 1)), 24.0);
+    }
+
+    #[test]
+    fn exempt_leaves_negative_prices_without_vat() {
+        let vat = Vat::flat(25.0, NegativePricePolicy::Exempt);
+        assert_eq!(vat.apply(day(2025, 1, 1), -4.0), -4.0);
+        assert_eq!(vat.apply(day(2025, 1, 1), 4.0), 5.0);
+    }
+
+    #[test]
+    fn apply_adds_vat_to_negative_prices() {
+        let vat = Vat::flat(25.0, NegativePricePolicy::Apply);
+        assert_eq!(vat.apply(day(2025, 1, 1), -4.0), -5.0);
+        assert_eq!(vat.apply(day(2025, 1, 1), 4.0), 5.0);
+    }
+
+    #[test]
+    fn exempt_only_by_default_in_finland() {
+        assert_eq!(
+            NegativePricePolicy::default_for("FI"),
+            NegativePricePolicy::Exempt
+        );
+        assert_eq!(
+            NegativePricePolicy::default_for("SE"),
+            NegativePricePolicy::Apply
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::NaiveDate;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::publisher::{self, DailyReport, ExistingPost, Publisher};
+
+// used without WEBHOOK_TEMPLATE, understood by e.g. Slack
+static DEFAULT_TEMPLATE: &str = r#"{"text": "{{text}}"}"#;
+
+static MAX_ATTEMPTS: u32 = 5;
+
+pub struct WebhookPublisher {
+    client: reqwest::Client,
+    url: String,
+    // scheme, host and port of the URL for logs and the post log, the path
+    // and query may have secrets
+    origin: String,
+    // delay before the first retry, doubled for each one after it
+    backoff: std::time::Duration,
+    template: Value,
+    secret: Option<String>,
+    chart_url: Option<String>,
+}
+
+// value at a dotted path like "aggregates.min.price"
+fn lookup<'a>(context: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(context, |value, key| value.get(key))
+}
+
+// A string that is only a placeholder, e.g. "{{prices}}", is replaced with the
+// value itself. Placeholders within longer strings are replaced with text.
+fn render(template: &Value, context: &Value) -> Result<Value, String> {
+    Ok(match template {
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if let Some(path) = trimmed
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|path| !path.contains("{{"))
+            {
+                lookup(context, path.trim())
+                    .ok_or(format!("Unknown webhook template value: {}", path))?
+                    .clone()
+            } else {
+                Value::String(interpolate(s, context)?)
+            }
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render(item, context))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render(value, context)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+fn interpolate(s: &str, context: &Value) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or(format!("Unclosed webhook template placeholder: {}", s))?;
+        let path = rest[start + 2..start + end].trim();
+        let value =
+            lookup(context, path).ok_or(format!("Unknown webhook template value: {}", path))?;
+
+        result.push_str(&rest[..start]);
+        match value {
+            Value::String(value) => result.push_str(value),
+            Value::Null => {}
+            value => result.push_str(&value.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+impl WebhookPublisher {
+    pub fn new(config: &Config) -> Result<WebhookPublisher, Box<dyn std::error::Error>> {
+        let template = match &config.webhook_template {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+
+        let url = config
+            .webhook_url
+            .clone()
+            .ok_or("WEBHOOK_URL must be set for the webhook publisher")?;
+        let origin = reqwest::Url::parse(&url)
+            .map_err(|_| "WEBHOOK_URL must be a valid URL")?
+            .origin()
+            .ascii_serialization();
+
+        Ok(WebhookPublisher {
+            client: reqwest::Client::new(),
+            url,
+            origin,
+            backoff: std::time::Duration::from_secs(1),
+            template: serde_json::from_str(&template)?,
+            secret: config.webhook_secret.clone(),
+            chart_url: config.webhook_chart_url.clone(),
+        })
+    }
+
+    fn context(&self, report: &DailyReport) -> Value {
+        let aggregates = &report.aggregates;
+        let mut text = report.fit_text(usize::MAX, |s| s.len());
+        if let Some(link) = &report.link {
+            text.push_str(&format!("\n\n{}", link));
+        }
+
+        json!({
+            "area": report.area,
+            "day": report.day.format("%Y-%m-%d").to_string(),
+            "day_title": report.day_title,
+            "text": text,
+            "tags": report.tags,
+            "link": report.link,
+            "unit": report.unit,
+            "prices": report.prices.iter().map(publisher::price_json).collect::<Vec<_>>(),
+            "aggregates": {
+                "min": publisher::price_json(&aggregates.min),
+                "max": publisher::price_json(&aggregates.max),
+                "avg": aggregates.avg,
+                "median": aggregates.median,
+                "p10": aggregates.p10,
+                "p90": aggregates.p90,
+                "base": aggregates.base,
+                "peak": aggregates.peak,
+                "off_peak": aggregates.off_peak,
+                "spread": aggregates.spread,
+                "std_dev": aggregates.std_dev,
+                "today_avg": aggregates.today_avg,
+                "last_week_avg": aggregates.last_week_avg,
+                "rolling_30d_avg": aggregates.rolling_30d_avg,
+                "cheapest_window": publisher::cheapest_json(&aggregates.cheapest_window),
+                "cheapest_slots": publisher::cheapest_json(&aggregates.cheapest_slots),
+            },
+            "alt_text": report.alt_text.fit(usize::MAX),
+            "chart_filename": report.image_filename,
+            "chart_url": self
+                .chart_url
+                .as_ref()
+                .map(|url| format!("{}/{}", url.trim_end_matches('/'), report.image_filename)),
+            "chart_base64": base64::engine::general_purpose::STANDARD.encode(&report.image),
+        })
+    }
+
+    fn body(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_string(&render(
+            &self.template,
+            &self.context(report),
+        )?)?)
+    }
+
+    // hex encoded HMAC-SHA256 of the body
+    fn sign(secret: &str, body: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(body.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl Publisher for WebhookPublisher {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    // webhooks can't be queried, so existing posts are only found in the
+    // local post log
+    async fn find_existing(
+        &self,
+        _area: &str,
+        _day: NaiveDate,
+        _day_title: &str,
+    ) -> Result<Option<ExistingPost>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn preview(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let mut context = self.context(report);
+        // the image would drown everything else in the preview
+        context["chart_base64"] = Value::String("...".to_string());
+        let body = render(&self.template, &context)?;
+        Ok(format!(
+            "POST {}/…\n{}",
+            self.origin,
+            serde_json::to_string_pretty(&body)?
+        ))
+    }
+
+    // retries network errors, rate limits and server errors with exponential backoff
+    async fn publish(&self, report: &DailyReport) -> Result<String, Box<dyn std::error::Error>> {
+        let body = self.body(report)?;
+
+        let mut attempt = 1;
+        loop {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+            if let Some(secret) = &self.secret {
+                request = request.header(
+                    "X-Spotbot-Signature",
+                    format!("sha256={}", Self::sign(secret, &body)),
+                );
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(self.origin.clone()),
+                Ok(response)
+                    if !response.status().is_server_error()
+                        && response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    return Err(format!("Webhook failed: {}", response.status()).into())
+                }
+                Ok(response) => format!("Webhook failed: {}", response.status()),
+                // the URL may have a secret, keep it out of the errors
+                Err(err) => format!("Webhook failed: {}", err.without_url()),
+            };
+
+            if attempt >= MAX_ATTEMPTS {
+                return Err(error.into());
+            }
+            let delay = self.backoff * (1 << (attempt - 1));
+            log::warn!("{}, retrying in {:?}", error, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // receivers get the corrected payload as a new request
+    fn supports_update(&self) -> bool {
+        true
+    }
+
+    async fn update(
+        &self,
+        _uri: &str,
+        report: &DailyReport,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.publish(report).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use crate::publisher::tests::sample_report;
+    use mockito::Matcher;
+
+    fn publisher(url: &str, secret: Option<&str>) -> WebhookPublisher {
+        let mut vars = vec![("WEBHOOK_URL", url)];
+        if let Some(secret) = secret {
+            vars.push(("WEBHOOK_SECRET", secret));
+        }
+        let mut publisher = WebhookPublisher::new(&config(&vars)).unwrap();
+        publisher.backoff = std::time::Duration::from_millis(1);
+        publisher
+    }
+
+    #[test]
+    fn templates_are_rendered() {
+        let context = json!({
+            "area": "FI",
+            "link": null,
+            "aggregates": { "avg": 3.5, "min": { "price": 1.25 } },
+            "prices": [1, 2],
+        });
+        let template = json!({
+            "prices": "{{prices}}",
+            "avg": " {{ aggregates.avg }} ",
+            "text": "{{area}}: {{aggregates.min.price}} c/kWh{{link}}",
+            "list": ["{{area}}", 1, true],
+            "nested": { "area": "{{area}}" },
+        });
+
+        assert_eq!(
+            render(&template, &context).unwrap(),
+            json!({
+                "prices": [1, 2],
+                "avg": 3.5,
+                "text": "FI: 1.25 c/kWh",
+                "list": ["FI", 1, true],
+                "nested": { "area": "FI" },
+            })
+        );
+        assert_eq!(
+            render(&json!("{{nope}}"), &context).unwrap_err(),
+            "Unknown webhook template value: nope"
+        );
+        assert!(render(&json!("a {{area} b"), &context)
+            .unwrap_err()
+            .starts_with("Unclosed"));
+    }
+
+    #[test]
+    fn signatures_are_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            WebhookPublisher::sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_in_the_url_stay_out_of_the_post_log() {
+        let mut server = mockito::Server::new_async().await;
+        let url = format!("{}/hooks/secret-token?key=secret", server.url());
+        let publisher = publisher(&url, None);
+
+        server
+            .mock("POST", "/hooks/secret-token")
+            .match_query(Matcher::UrlEncoded("key".to_string(), "secret".to_string()))
+            .create_async()
+            .await;
+
+        let report = sample_report();
+        let uri = publisher.publish(&report).await.unwrap();
+        assert_eq!(uri, server.url());
+        assert!(!publisher.preview(&report).await.unwrap().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = publisher(&format!("{}/hook", server.url()), Some("secret"));
+        let body = publisher.body(&sample_report()).unwrap();
+        let signature = format!("sha256={}", WebhookPublisher::sign("secret", &body));
+
+        let failing = server
+            .mock("POST", "/hook")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let succeeding = server
+            .mock("POST", "/hook")
+            .match_header("x-spotbot-signature", signature.as_str())
+            .match_header("content-type", "application/json")
+            .match_body(Matcher::Exact(body))
+            .create_async()
+            .await;
+
+        publisher.publish(&sample_report()).await.unwrap();
+        failing.assert_async().await;
+        succeeding.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn retries_end() {
+        let mut server = mockito::Server::new_async().await;
+        let publisher = publisher(&format!("{}/hook", server.url()), None);
+
+        let unavailable = server
+            .mock("POST", "/hook")
+            .with_status(503)
+            .expect(MAX_ATTEMPTS as usize)
+            .create_async()
+            .await;
+        let error = publisher.publish(&sample_report()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Webhook failed: 503 Service Unavailable");
+        unavailable.assert_async().await;
+
+        // client errors aren't retried
+        let rejected = server
+            .mock("POST", "/hook")
+            .with_status(400)
+            .expect(1)
+            .create_async()
+            .await;
+        unavailable.remove_async().await;
+        assert!(publisher.publish(&sample_report()).await.is_err());
+        rejected.assert_async().await;
+    }
+}